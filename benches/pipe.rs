@@ -26,6 +26,82 @@ fn benchmark(c: &mut Criterion) {
             BatchSize::SmallInput,
         )
     });
+
+    c.bench_function("write 100 1K chunks, copy_into_buf", |b| {
+        use futures::prelude::*;
+
+        let data = [1; 1024];
+
+        b.iter_batched(
+            sluice::pipe::pipe,
+            |(reader, mut writer)| {
+                let producer = async {
+                    for _ in 0u8..100 {
+                        writer.write_all(&data).await.unwrap();
+                    }
+                    writer.close().await.unwrap();
+                };
+
+                let consumer = async {
+                    reader.copy_into_buf(futures::io::sink()).await.unwrap();
+                };
+
+                futures::executor::block_on(future::join(producer, consumer));
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    c.bench_function("read 100 64B chunks into a large buffer", |b| {
+        use futures::prelude::*;
+
+        let data = [1; 64];
+
+        b.iter_batched(
+            || sluice::pipe::PipeBuilder::new().chunk_count(100).build(),
+            |(mut reader, mut writer)| {
+                futures::executor::block_on(async {
+                    for _ in 0u8..100 {
+                        writer.write_all(&data).await.unwrap();
+                    }
+                    writer.close().await.unwrap();
+
+                    let mut buf = [0; 8192];
+                    while reader.read(&mut buf).await.unwrap() > 0 {}
+                });
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    for lifo in [false, true] {
+        let name = if lifo {
+            "ping-pong 1000 1K chunks, LIFO reuse"
+        } else {
+            "ping-pong 1000 1K chunks, FIFO reuse"
+        };
+
+        c.bench_function(name, |b| {
+            use futures::prelude::*;
+
+            let data = [1; 1024];
+
+            b.iter_batched(
+                || sluice::pipe::PipeBuilder::new().chunk_count(16).lifo_chunk_reuse(lifo).build(),
+                |(mut reader, mut writer)| {
+                    futures::executor::block_on(async {
+                        let mut buf = [0; 1024];
+
+                        for _ in 0..1000 {
+                            writer.write_all(&data).await.unwrap();
+                            reader.read_exact(&mut buf).await.unwrap();
+                        }
+                    });
+                },
+                BatchSize::SmallInput,
+            )
+        });
+    }
 }
 
 criterion_group!(benches, benchmark);
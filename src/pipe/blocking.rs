@@ -0,0 +1,69 @@
+//! Adapters for using an asynchronous pipe from synchronous code.
+
+use super::PipeReader;
+use futures_io::AsyncRead;
+use std::{
+    io::{self, Read},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+};
+
+/// A pipe reader that implements the blocking [`Read`] trait.
+///
+/// Each read blocks the current thread until bytes are available, parking the
+/// thread while it waits for the writer. No async runtime is required, which
+/// makes this suitable for consuming a pipe from a thread pool doing
+/// synchronous work while the writer is driven by async code elsewhere.
+#[derive(Debug)]
+pub struct BlockingReader {
+    inner: PipeReader,
+}
+
+impl BlockingReader {
+    /// Wrap an asynchronous pipe reader for blocking use.
+    pub fn new(reader: PipeReader) -> Self {
+        Self { inner: reader }
+    }
+
+    /// Get the underlying asynchronous pipe reader.
+    pub fn into_inner(self) -> PipeReader {
+        self.inner
+    }
+}
+
+impl Read for BlockingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        block_on(|cx| Pin::new(&mut self.inner).poll_read(cx, buf))
+    }
+}
+
+/// Waker that unparks the thread that created it.
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Poll the given function on the current thread until it is ready, parking
+/// the thread in between polls.
+fn block_on<T>(mut poll: impl FnMut(&mut Context<'_>) -> Poll<T>) -> T {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        if let Poll::Ready(value) = poll(&mut cx) {
+            return value;
+        }
+
+        // Parking may wake up spuriously, in which case we just poll again.
+        thread::park();
+    }
+}
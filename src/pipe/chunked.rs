@@ -20,54 +20,334 @@
 //! that happen during reads and writes are occasional reallocation for each
 //! individual vector to fit larger chunks of bytes that don't already fit.
 
-use async_channel::{bounded, Sender, Receiver};
+use super::{
+    rate::{Budget, Limiter},
+    PipeBuilder,
+};
+#[cfg(feature = "metrics")]
+use super::PipeStats;
+#[cfg(feature = "metrics")]
+use std::sync::atomic::AtomicU64;
+use async_channel::{bounded, Receiver, Sender, TryRecvError, TrySendError};
 use futures_core::{FusedStream, Stream};
 use futures_io::{AsyncBufRead, AsyncRead, AsyncWrite};
 use std::{
     io,
     io::{BufRead, Cursor, Write},
     pin::Pin,
-    task::{Context, Poll},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+        Mutex,
+    },
+    task::{ready, Context, Poll, Waker},
 };
 
 /// Create a new chunked pipe with room for a fixed number of chunks.
 ///
-/// The `count` parameter sets how many buffers are available in the pipe at
-/// once. Smaller values will reduce the number of allocations and reallocations
-/// may be required when writing and reduce overall memory usage. Larger values
+/// The chunk count sets how many buffers are available in the pipe at once.
+/// Smaller values will reduce the number of allocations and reallocations may
+/// be required when writing and reduce overall memory usage. Larger values
 /// reduce the amount of waiting done between chunks if you have a producer and
 /// consumer that run at different speeds.
 ///
-/// If `count` is set to 1, then the pipe is essentially serial, since only the
-/// reader or writer can operate on the single buffer at one time and cannot be
-/// run in parallel.
-pub(crate) fn new(count: usize) -> (Reader, Writer) {
+/// If the chunk count is set to 1, then the pipe is essentially serial, since
+/// only the reader or writer can operate on the single buffer at one time and
+/// cannot be run in parallel.
+pub(crate) fn new(builder: &PipeBuilder) -> (Reader, Writer) {
+    let count = builder.chunk_count;
     let (buf_pool_tx, buf_pool_rx) = bounded(count);
     let (buf_stream_tx, buf_stream_rx) = bounded(count);
 
     // Fill up the buffer pool.
     for _ in 0..count {
         buf_pool_tx
-            .try_send(Cursor::new(Vec::new()))
+            .try_send(Cursor::new(Vec::with_capacity(builder.initial_chunk_capacity)))
             .expect("buffer pool overflow");
     }
 
+    let shared = Arc::new(Shared::default());
+
     let reader = Reader {
+        shared: shared.clone(),
         buf_pool_tx,
         buf_stream_rx,
         chunk: None,
+        position: 0,
+        chunk_count: count,
+        max_retained_capacity: builder.max_chunk_retained_capacity,
     };
 
     let writer = Writer {
+        shared,
         buf_pool_rx,
-        buf_stream_tx,
+        buf_stream_tx: Some(buf_stream_tx),
+        rendezvous: builder.rendezvous,
+        position: 0,
+        chunk_count: count,
+        coalesce_threshold: builder.coalesce_threshold,
+        pending: None,
+        lifo: builder.lifo_chunk_reuse,
+        limiter: builder.rate_limit.clone().map(Limiter::new),
     };
 
     (reader, writer)
 }
 
+/// Return a pipe to a fresh, empty state while keeping its chunk buffers, so
+/// that it can be reused.
+///
+/// Fails if the reader and writer are not the only two halves of the same
+/// pipe. Any bytes still in the pipe are discarded.
+pub(crate) fn reset(reader: &mut Reader, writer: &mut Writer) -> io::Result<()> {
+    if !Arc::ptr_eq(&reader.shared, &writer.shared) || Arc::strong_count(&reader.shared) != 2 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "reader and writer are not the only halves of the same pipe",
+        ));
+    }
+
+    // Gather up every buffer, wherever it currently is.
+    let mut chunks: Vec<_> = reader.chunk.take().into_iter().chain(writer.pending.take()).collect();
+    chunks.append(&mut writer.shared.spares.lock().unwrap());
+    chunks.extend(std::iter::from_fn(|| writer.buf_pool_rx.try_recv().ok()));
+    chunks.extend(std::iter::from_fn(|| reader.buf_stream_rx.try_recv().ok()));
+
+    let count = reader.chunk_count;
+    let (buf_pool_tx, buf_pool_rx) = bounded(count);
+    let (buf_stream_tx, buf_stream_rx) = bounded(count);
+
+    debug_assert_eq!(chunks.len(), count, "chunk buffers missing or duplicated");
+
+    for mut chunk in chunks.into_iter().take(count) {
+        chunk.set_position(0);
+        chunk.get_mut().clear();
+
+        if let Some(max) = reader.max_retained_capacity {
+            if chunk.get_ref().capacity() > max {
+                *chunk.get_mut() = Vec::with_capacity(max);
+            }
+        }
+
+        buf_pool_tx.try_send(chunk).expect("buffer pool overflow");
+    }
+
+    // Both halves are accounted for, so no buffers can be missing. Top up the
+    // pool just in case.
+    while buf_pool_tx.len() < count {
+        buf_pool_tx
+            .try_send(Cursor::new(Vec::new()))
+            .expect("buffer pool overflow");
+    }
+
+    let shared = Arc::new(Shared::default());
+
+    reader.shared = shared.clone();
+    reader.buf_pool_tx = buf_pool_tx;
+    reader.buf_stream_rx = buf_stream_rx;
+    reader.position = 0;
+
+    writer.shared = shared;
+    writer.buf_pool_rx = buf_pool_rx;
+    writer.buf_stream_tx = Some(buf_stream_tx);
+    writer.position = 0;
+
+    Ok(())
+}
+
+/// State shared between the reader and the writer outside of the chunk
+/// channels.
+#[derive(Default)]
+struct Shared {
+    /// Number of bytes sent by the writer not yet consumed by the reader.
+    buffered: AtomicUsize,
+
+    /// Counters for observing pipe activity.
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+
+    /// Set while the reader is waiting for a new chunk to arrive.
+    reader_waiting: AtomicBool,
+
+    /// Set once the reader has reached the end of the pipe.
+    eof_observed: AtomicBool,
+
+    /// Rate limit budget shared by every clone of the writer.
+    rate_budget: Budget,
+
+    /// Buffers taken from the pool by a writer that have not been written to
+    /// yet. These are shared so that cloned writers can all use them.
+    spares: Mutex<Vec<Cursor<Vec<u8>>>>,
+
+    /// Number of buffers taken out of the channels and not yet put back, used
+    /// to check that buffers are never lost or duplicated.
+    #[cfg(any(debug_assertions, feature = "chunk-accounting"))]
+    chunks_out: AtomicUsize,
+
+    /// Wakers for writers waiting for the reader to become idle.
+    idle_wakers: WakerList,
+
+    /// Wakers for writers waiting for the reader to be dropped.
+    reader_dropped_wakers: WakerList,
+
+    /// Waker for the reader waiting for the writer to close the pipe.
+    writer_closed_wakers: WakerList,
+
+    /// Wakers for writers waiting for the reader to reach the end of the pipe.
+    eof_observed_wakers: WakerList,
+}
+
+impl Shared {
+    fn set_reader_waiting(&self, waiting: bool) {
+        self.reader_waiting.store(waiting, Ordering::SeqCst);
+
+        if waiting {
+            self.idle_wakers.wake();
+        }
+    }
+
+    fn set_eof_observed(&self) {
+        if !self.eof_observed.swap(true, Ordering::SeqCst) {
+            self.eof_observed_wakers.wake();
+        }
+    }
+
+    /// Account for a buffer taken out of one of the channels.
+    ///
+    /// The count is increased only after a buffer is taken and decreased
+    /// before one is put back, so it never counts a buffer that is sitting in
+    /// a channel. It can therefore never exceed the number of buffers in the
+    /// pipe, even while the other half is busy.
+    #[cfg_attr(not(any(debug_assertions, feature = "chunk-accounting")), allow(unused_variables))]
+    fn chunk_taken(&self, chunk_count: usize) {
+        #[cfg(any(debug_assertions, feature = "chunk-accounting"))]
+        {
+            let out = self.chunks_out.fetch_add(1, Ordering::SeqCst) + 1;
+            assert!(out <= chunk_count, "more chunk buffers in use than exist");
+        }
+    }
+
+    /// Account for a buffer about to be put back into one of the channels.
+    ///
+    /// If the buffer cannot be sent after all, `chunk_taken` must be called
+    /// again to count it as still out.
+    fn chunk_returning(&self) {
+        #[cfg(any(debug_assertions, feature = "chunk-accounting"))]
+        self.chunks_out.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Lifetime counters of pipe activity.
+#[cfg(feature = "metrics")]
+#[derive(Default)]
+pub(crate) struct Metrics {
+    bytes_written: AtomicU64,
+    bytes_read: AtomicU64,
+    chunks_written: AtomicU64,
+    writer_waits: AtomicU64,
+    reader_waits: AtomicU64,
+    writes: AtomicU64,
+    write_bytes: AtomicU64,
+    min_write: AtomicU64,
+    max_write: AtomicU64,
+    min_chunk: AtomicU64,
+    max_chunk: AtomicU64,
+}
+
+#[cfg(feature = "metrics")]
+impl Metrics {
+    pub(crate) fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn chunks_written(&self) -> u64 {
+        self.chunks_written.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn writer_waits(&self) -> u64 {
+        self.writer_waits.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn reader_waits(&self) -> u64 {
+        self.reader_waits.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn stats(&self) -> PipeStats {
+        let writes = self.writes.load(Ordering::Relaxed);
+        let chunks = self.chunks_written();
+
+        PipeStats {
+            writes,
+            min_write: self.min_write.load(Ordering::Relaxed),
+            max_write: self.max_write.load(Ordering::Relaxed),
+            avg_write: self.write_bytes.load(Ordering::Relaxed).checked_div(writes).unwrap_or(0),
+            chunks,
+            min_chunk: self.min_chunk.load(Ordering::Relaxed),
+            max_chunk: self.max_chunk.load(Ordering::Relaxed),
+            avg_chunk: self.bytes_written().checked_div(chunks).unwrap_or(0),
+        }
+    }
+
+    fn record_write(&self, len: usize) {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.write_bytes.fetch_add(len as u64, Ordering::Relaxed);
+        record_range(&self.min_write, &self.max_write, len as u64);
+    }
+
+    fn record_chunk(&self, len: usize) {
+        self.bytes_written.fetch_add(len as u64, Ordering::Relaxed);
+        self.chunks_written.fetch_add(1, Ordering::Relaxed);
+        record_range(&self.min_chunk, &self.max_chunk, len as u64);
+    }
+}
+
+/// Widen the range held by a pair of counters to include the given nonzero
+/// value, where a minimum of zero means that nothing has been recorded yet.
+#[cfg(feature = "metrics")]
+fn record_range(
+    min: &AtomicU64,
+    max: &AtomicU64,
+    value: u64,
+) {
+    let _ = min.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+        if current == 0 || value < current {
+            Some(value)
+        } else {
+            None
+        }
+    });
+    max.fetch_max(value, Ordering::Relaxed);
+}
+
+/// The wakers of tasks waiting on the other half of the pipe. Cloned writers
+/// can all be waiting at once, so every registered task is woken.
+#[derive(Default)]
+struct WakerList(Mutex<Vec<Waker>>);
+
+impl WakerList {
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.0.lock().unwrap();
+
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+
+    fn wake(&self) {
+        for waker in std::mem::take(&mut *self.0.lock().unwrap()) {
+            waker.wake();
+        }
+    }
+}
+
 /// The reading half of a chunked pipe.
 pub(crate) struct Reader {
+    shared: Arc<Shared>,
+
     /// A channel of incoming chunks from the writer.
     buf_pool_tx: Sender<Cursor<Vec<u8>>>,
 
@@ -76,31 +356,57 @@ pub(crate) struct Reader {
 
     /// A chunk currently being read from.
     chunk: Option<Cursor<Vec<u8>>>,
+
+    /// Total number of bytes consumed by this reader.
+    position: u64,
+
+    /// Total number of chunk buffers in circulation.
+    chunk_count: usize,
+
+    /// Maximum capacity a chunk buffer may keep when returned for reuse.
+    max_retained_capacity: Option<usize>,
 }
 
 impl AsyncRead for Reader {
     fn poll_read(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
-        mut buf: &mut [u8],
+        buf: &mut [u8],
     ) -> Poll<io::Result<usize>> {
-        // Read into the internal buffer.
-        match self.as_mut().poll_fill_buf(cx)? {
-            // Not quite ready yet.
-            Poll::Pending => Poll::Pending,
+        let mut total = 0;
+
+        while total < buf.len() {
+            // Once we've read something, only keep going if more bytes are
+            // available right now so that we never wait while holding bytes
+            // that could be returned.
+            if total > 0 && !self.has_chunk_ready() {
+                break;
+            }
 
-            // A chunk is available.
-            Poll::Ready(chunk) => {
-                // Copy as much of the chunk as we can to the destination
-                // buffer.
-                let amt = buf.write(chunk)?;
+            // Read into the internal buffer.
+            match self.as_mut().poll_fill_buf(cx)? {
+                // Not quite ready yet.
+                Poll::Pending if total == 0 => return Poll::Pending,
+                Poll::Pending => break,
 
-                // Mark however much was successfully copied as being consumed.
-                self.consume(amt);
+                // End of the pipe.
+                Poll::Ready([]) => break,
 
-                Poll::Ready(Ok(amt))
+                // A chunk is available.
+                Poll::Ready(chunk) => {
+                    // Copy as much of the chunk as we can to the destination
+                    // buffer.
+                    let amt = (&mut buf[total..]).write(chunk)?;
+
+                    // Mark however much was successfully copied as being
+                    // consumed.
+                    self.as_mut().consume(amt);
+                    total += amt;
+                }
             }
         }
+
+        Poll::Ready(Ok(total))
     }
 }
 
@@ -108,48 +414,39 @@ impl AsyncBufRead for Reader {
     fn poll_fill_buf(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
         // If the current chunk is consumed, first return it to the writer for
         // reuse.
-        if let Some(chunk) = self.chunk.as_ref() {
-            if chunk.position() >= chunk.get_ref().len() as u64 {
-                let mut chunk = self.chunk.take().unwrap();
-                chunk.set_position(0);
-                chunk.get_mut().clear();
-
-                if let Err(e) = self.buf_pool_tx.try_send(chunk) {
-                    // We pre-fill the buffer pool channel with an exact number
-                    // of buffers, so this can never happen.
-                    if e.is_full() {
-                        panic!("buffer pool overflow")
-                    }
-                    // If the writer disconnects, then we'll just discard this
-                    // buffer and any subsequent buffers until we've read
-                    // everything still in the pipe.
-                    else if e.is_closed() {
-                        // Nothing!
-                    }
-                    // Some other error occurred.
-                    else {
-                        return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
-                    }
-                }
-            }
-        }
+        self.recycle_consumed_chunk();
 
         // If we have no current chunk, then attempt to read one.
         if self.chunk.is_none() {
             // If the stream has terminated, then do not poll it again.
             if self.buf_stream_rx.is_terminated() {
+                self.shared.set_eof_observed();
                 return Poll::Ready(Ok(&[]));
             }
 
             match Pin::new(&mut self.buf_stream_rx).poll_next(cx) {
                 // Wait for a new chunk to be delivered.
-                Poll::Pending => return Poll::Pending,
+                Poll::Pending => {
+                    #[cfg(feature = "metrics")]
+                    self.shared.metrics.reader_waits.fetch_add(1, Ordering::Relaxed);
+
+                    self.shared.set_reader_waiting(true);
+                    return Poll::Pending;
+                }
 
                 // Pipe has closed, so return EOF.
-                Poll::Ready(None) => return Poll::Ready(Ok(&[])),
+                Poll::Ready(None) => {
+                    self.shared.set_reader_waiting(false);
+                    self.shared.set_eof_observed();
+                    return Poll::Ready(Ok(&[]));
+                }
 
                 // Accept the new chunk.
-                Poll::Ready(buf) => self.chunk = buf,
+                Poll::Ready(buf) => {
+                    self.shared.set_reader_waiting(false);
+                    self.shared.chunk_taken(self.chunk_count);
+                    self.chunk = buf;
+                }
             }
         }
 
@@ -163,8 +460,218 @@ impl AsyncBufRead for Reader {
 
     fn consume(mut self: Pin<&mut Self>, amt: usize) {
         if let Some(chunk) = self.chunk.as_mut() {
-            // Consume the requested amount from the current chunk.
+            // Consume the requested amount from the current chunk, without
+            // going past the end.
+            let remaining = chunk.get_ref().len() - chunk.position() as usize;
+            let amt = amt.min(remaining);
             chunk.consume(amt);
+
+            self.shared.buffered.fetch_sub(amt, Ordering::SeqCst);
+            self.position += amt as u64;
+
+            #[cfg(feature = "metrics")]
+            self.shared.metrics.bytes_read.fetch_add(amt as u64, Ordering::Relaxed);
+        }
+
+        // Hand the chunk back right away if we're done with it, since the
+        // writer might be waiting on it.
+        self.recycle_consumed_chunk();
+    }
+}
+
+impl Reader {
+    /// Wait until the writer has closed the pipe and every byte written has
+    /// been consumed.
+    pub(crate) fn poll_eof(&self, cx: &mut Context<'_>) -> Poll<()> {
+        // Register for a wakeup before checking so that we cannot miss the
+        // writer closing.
+        self.shared.writer_closed_wakers.register(cx.waker());
+
+        if self.buf_stream_rx.is_closed() && self.buffered_len() == 0 {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Wait until the current chunk holds at least `n` unread bytes, or the
+    /// pipe is closed.
+    ///
+    /// Incoming chunks are merged into the current chunk, and their buffers
+    /// returned to the writer, so that the writer can keep going even if `n`
+    /// is more than the pipe's chunks would otherwise hold.
+    pub(crate) fn poll_fill_at_least(&mut self, cx: &mut Context<'_>, n: usize) -> Poll<io::Result<()>> {
+        loop {
+            let available = match self.chunk.as_ref() {
+                Some(chunk) => chunk.get_ref().len() - chunk.position() as usize,
+                None => 0,
+            };
+
+            if available >= n || self.buf_stream_rx.is_terminated() {
+                return Poll::Ready(Ok(()));
+            }
+
+            match Pin::new(&mut self.buf_stream_rx).poll_next(cx) {
+                Poll::Pending => {
+                    #[cfg(feature = "metrics")]
+                    self.shared.metrics.reader_waits.fetch_add(1, Ordering::Relaxed);
+
+                    self.shared.set_reader_waiting(true);
+                    return Poll::Pending;
+                }
+
+                // Pipe has closed, so there will never be more.
+                Poll::Ready(None) => {
+                    self.shared.set_reader_waiting(false);
+                    return Poll::Ready(Ok(()));
+                }
+
+                Poll::Ready(Some(next)) => {
+                    self.shared.set_reader_waiting(false);
+                    self.shared.chunk_taken(self.chunk_count);
+
+                    match self.chunk.as_mut() {
+                        None => self.chunk = Some(next),
+
+                        // Move the unread bytes to the front of the current
+                        // chunk and append the new chunk to them.
+                        Some(chunk) => {
+                            let position = chunk.position() as usize;
+                            chunk.get_mut().drain(..position);
+                            chunk.set_position(0);
+                            chunk.get_mut().extend_from_slice(next.get_ref());
+
+                            self.recycle(next);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Consume every byte that can be read without waiting and return them all
+    /// at once.
+    pub(crate) fn drain_remaining(&mut self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.buffered_len());
+
+        loop {
+            if let Some(chunk) = self.chunk.as_ref() {
+                let remaining = &chunk.get_ref()[chunk.position() as usize..];
+                let amt = remaining.len();
+                data.extend_from_slice(remaining);
+                Pin::new(&mut *self).consume(amt);
+            }
+
+            match self.buf_stream_rx.try_recv() {
+                Ok(chunk) => {
+                    self.shared.chunk_taken(self.chunk_count);
+                    self.chunk = Some(chunk);
+                }
+                // The writer is done, so there is nothing left to read. This
+                // is only reported once the channel is empty, so that a final
+                // chunk sent just before closing is not skipped.
+                Err(TryRecvError::Closed) => {
+                    self.shared.set_eof_observed();
+                    break;
+                }
+
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        data
+    }
+
+    /// Check if a chunk can be read from without waiting.
+    pub(crate) fn has_chunk_ready(&self) -> bool {
+        self.chunk.is_some() || !self.buf_stream_rx.is_empty()
+    }
+
+    /// Check if the writer has closed the pipe.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.buf_stream_rx.is_closed()
+    }
+
+    /// Check that every chunk buffer is accounted for, assuming that neither
+    /// half of the pipe is in the middle of an operation.
+    #[cfg(feature = "chunk-accounting")]
+    pub(crate) fn assert_chunk_accounting(&self) {
+        let in_channels = self.buf_pool_tx.len() + self.buf_stream_rx.len();
+        let out = self.shared.chunks_out.load(Ordering::SeqCst);
+
+        assert_eq!(
+            in_channels + out,
+            self.chunk_count,
+            "{} chunk buffers in channels and {} out, expected {} in total",
+            in_channels,
+            out,
+            self.chunk_count,
+        );
+    }
+
+    /// Get the number of chunks holding bytes that have not been consumed yet.
+    pub(crate) fn unread_chunks(&self) -> usize {
+        self.buf_stream_rx.len() + self.chunk.is_some() as usize
+    }
+
+    /// Get the number of bytes written that have not yet been consumed.
+    pub(crate) fn buffered_len(&self) -> usize {
+        self.shared.buffered.load(Ordering::SeqCst)
+    }
+
+    /// Get the total number of chunk buffers in circulation.
+    pub(crate) fn chunk_count(&self) -> usize {
+        self.chunk_count
+    }
+
+    /// Get the total number of bytes consumed by this reader.
+    pub(crate) fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Get the activity counters of this pipe.
+    #[cfg(feature = "metrics")]
+    pub(crate) fn metrics(&self) -> &Metrics {
+        &self.shared.metrics
+    }
+
+    /// If the current chunk has been fully consumed, return it to the writer
+    /// for reuse.
+    fn recycle_consumed_chunk(&mut self) {
+        if let Some(chunk) = self.chunk.as_ref() {
+            if chunk.position() >= chunk.get_ref().len() as u64 {
+                let chunk = self.chunk.take().unwrap();
+                self.recycle(chunk);
+            }
+        }
+    }
+
+    /// Return a chunk buffer to the writer for reuse.
+    fn recycle(&self, mut chunk: Cursor<Vec<u8>>) {
+        chunk.set_position(0);
+        chunk.get_mut().clear();
+
+        // Release excess memory left behind by an unusually large chunk.
+        if let Some(max) = self.max_retained_capacity {
+            if chunk.get_ref().capacity() > max {
+                *chunk.get_mut() = Vec::with_capacity(max);
+            }
+        }
+
+        self.shared.chunk_returning();
+
+        if let Err(e) = self.buf_pool_tx.try_send(chunk) {
+            self.shared.chunk_taken(self.chunk_count);
+
+            // We pre-fill the buffer pool channel with an exact number of
+            // buffers, so this can never happen.
+            if e.is_full() {
+                panic!("buffer pool overflow")
+            }
+
+            // Otherwise the writer has disconnected, so we'll just discard
+            // this buffer and any subsequent buffers until we've read
+            // everything still in the pipe.
         }
     }
 }
@@ -176,28 +683,361 @@ impl Drop for Reader {
         // pool channel.
         self.buf_stream_rx.close();
         self.buf_pool_tx.close();
+
+        // Wake a writer waiting on us so that it can observe that we are gone.
+        self.shared.idle_wakers.wake();
+        self.shared.reader_dropped_wakers.wake();
+        self.shared.eof_observed_wakers.wake();
     }
 }
 
 /// Writing half of a chunked pipe.
 pub(crate) struct Writer {
+    shared: Arc<Shared>,
+
     /// A channel of chunks to send to the reader.
     buf_pool_rx: Receiver<Cursor<Vec<u8>>>,
 
-    /// A channel of incoming buffers to write chunks to.
-    buf_stream_tx: Sender<Cursor<Vec<u8>>>,
+    /// A channel of incoming buffers to write chunks to. Set to `None` once
+    /// this writer is closed, while other clones may still be writing.
+    buf_stream_tx: Option<Sender<Cursor<Vec<u8>>>>,
+
+    /// If set, writes do not complete until the reader has consumed the
+    /// written chunk.
+    rendezvous: bool,
+
+    /// Total number of bytes accepted by this writer.
+    position: u64,
+
+    /// Total number of chunk buffers in circulation.
+    chunk_count: usize,
+
+    /// If set, small writes are combined into a single chunk until it reaches
+    /// this many bytes.
+    coalesce_threshold: Option<usize>,
+
+    /// A chunk holding coalesced writes that has not been sent yet.
+    pending: Option<Cursor<Vec<u8>>>,
+
+    /// If set, the most recently returned buffer is reused first.
+    lifo: bool,
+
+    /// Paces writes if the pipe has a rate limit.
+    limiter: Option<Limiter>,
 }
 
-impl AsyncWrite for Writer {
-    fn poll_write(
-        mut self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-        buf: &[u8],
-    ) -> Poll<io::Result<usize>> {
+impl Writer {
+    /// Wait until the reader has been dropped.
+    pub(crate) fn poll_reader_dropped(&self, cx: &mut Context<'_>) -> Poll<()> {
+        // Register for a wakeup before checking so that we cannot miss the
+        // reader being dropped.
+        self.shared.reader_dropped_wakers.register(cx.waker());
+
+        // Only the reader ever closes the pool channel.
+        if self.buf_pool_rx.is_closed() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Wait until the reader has reached the end of the pipe after it was
+    /// closed.
+    pub(crate) fn poll_eof_observed(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Register for a wakeup before checking so that we cannot miss the
+        // reader reaching the end.
+        self.shared.eof_observed_wakers.register(cx.waker());
+
+        if self.shared.eof_observed.load(Ordering::SeqCst) {
+            Poll::Ready(Ok(()))
+        } else if self.buf_pool_rx.is_closed() {
+            // Reader was dropped before reaching the end.
+            Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()))
+        } else {
+            Poll::Pending
+        }
+    }
+
+    /// Check if the pipe has been closed, either by the writer or because the
+    /// reader was dropped.
+    pub(crate) fn is_closed(&self) -> bool {
+        match self.buf_stream_tx.as_ref() {
+            Some(tx) => tx.is_closed(),
+            None => true,
+        }
+    }
+
+    /// Get the number of bytes written that have not yet been consumed.
+    pub(crate) fn buffered_len(&self) -> usize {
+        self.shared.buffered.load(Ordering::SeqCst)
+    }
+
+    /// Get the total number of chunk buffers in circulation.
+    pub(crate) fn chunk_count(&self) -> usize {
+        self.chunk_count
+    }
+
+    /// Get the number of chunk buffers that can be written to without
+    /// waiting.
+    pub(crate) fn available_chunks(&self) -> usize {
+        self.buf_pool_rx.len() + self.shared.spares.lock().unwrap().len()
+    }
+
+    /// Get the total number of bytes accepted by this writer.
+    pub(crate) fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Get the activity counters of this pipe.
+    #[cfg(feature = "metrics")]
+    pub(crate) fn metrics(&self) -> &Metrics {
+        &self.shared.metrics
+    }
+
+    /// Wait until a buffer is available to write a chunk to without waiting.
+    pub(crate) fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.is_closed() {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+
+        let mut spares = self.shared.spares.lock().unwrap();
+
+        if !spares.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+
+        // Take a buffer out of the pool and set it aside as a spare, so that
+        // the next write is guaranteed to find it.
+        match Pin::new(&mut self.buf_pool_rx).poll_next(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(None) => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+            Poll::Ready(Some(chunk)) => {
+                self.shared.chunk_taken(self.chunk_count);
+                spares.push(chunk);
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+
+    /// Wait until every chunk sent so far has been consumed by the reader and
+    /// returned to the pool.
+    pub(crate) fn poll_all_consumed(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // Coalesced writes have to be sent before they can be consumed.
+        if let Err(e) = self.send_pending() {
+            return Poll::Ready(Err(e));
+        }
+
+        // Hold the lock while polling the pool so that a cloned writer cannot
+        // take the last buffer between our check and registering for a
+        // wakeup.
+        let mut spares = self.shared.spares.lock().unwrap();
+
+        while spares.len() < self.chunk_count {
+            match Pin::new(&mut self.buf_pool_rx).poll_next(cx) {
+                Poll::Pending => return Poll::Pending,
+
+                // Reader was dropped before consuming every chunk.
+                Poll::Ready(None) => return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+
+                Poll::Ready(Some(chunk)) => {
+                    self.shared.chunk_taken(self.chunk_count);
+                    spares.push(chunk);
+                }
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    /// Stop sending chunks from this writer. The pipe is closed once every
+    /// clone of the writer has closed its stream.
+    fn close_stream(&mut self) {
+        // Drop the sender now instead of when the writer is dropped so that a
+        // woken reader is guaranteed to see the pipe closed if this was the
+        // last writer.
+        self.buf_stream_tx = None;
+        self.shared.writer_closed_wakers.wake();
+    }
+
+    /// Send the chunk holding coalesced writes to the reader, if any.
+    fn send_pending(&mut self) -> io::Result<()> {
+        match self.pending.take() {
+            Some(chunk) => self.send_chunk(chunk),
+            None => Ok(()),
+        }
+    }
+
+    /// Send a filled chunk to the reader.
+    fn send_chunk(&mut self, chunk: Cursor<Vec<u8>>) -> io::Result<()> {
+        let len = chunk.get_ref().len();
+
+        // Account for the bytes first so that the reader never consumes bytes
+        // that are not counted yet.
+        self.shared.buffered.fetch_add(len, Ordering::SeqCst);
+
+        self.shared.chunk_returning();
+
+        let result = match self.buf_stream_tx.as_ref() {
+            Some(tx) => tx.try_send(chunk),
+            None => Err(TrySendError::Closed(chunk)),
+        };
+
+        if let Err(e) = result {
+            self.shared.chunk_taken(self.chunk_count);
+            self.shared.buffered.fetch_sub(len, Ordering::SeqCst);
+
+            // We pre-fill the buffer pool channel with an exact number of
+            // buffers, so the stream can never be full.
+            if e.is_full() {
+                panic!("buffer pool overflow")
+            }
+
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        #[cfg(feature = "metrics")]
+        self.shared.metrics.record_chunk(len);
+
+        Ok(())
+    }
+
+    /// Wait until every chunk sent so far has been consumed and the reader is
+    /// waiting for more.
+    pub(crate) fn poll_idle(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_all_consumed(cx))?;
+
+        // Register for a wakeup before checking the reader state so that we
+        // cannot miss the reader starting to wait.
+        self.shared.idle_wakers.register(cx.waker());
+
+        if self.is_closed() {
+            Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()))
+        } else if self.shared.reader_waiting.load(Ordering::SeqCst) {
+            Poll::Ready(Ok(()))
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Clone for Writer {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            buf_pool_rx: self.buf_pool_rx.clone(),
+            buf_stream_tx: self.buf_stream_tx.clone(),
+            rendezvous: self.rendezvous,
+            position: 0,
+            chunk_count: self.chunk_count,
+                coalesce_threshold: self.coalesce_threshold,
+            pending: None,
+            lifo: self.lifo,
+            limiter: self.limiter.clone(),
+        }
+    }
+}
+
+impl Writer {
+    /// Wait for an available buffer to write a chunk to.
+    fn poll_acquire_chunk(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Cursor<Vec<u8>>>> {
+        let spare = {
+            let mut spares = self.shared.spares.lock().unwrap();
+
+            // Buffers come out of the pool in the order they were returned, so
+            // take all of them to get to the newest one.
+            if self.lifo {
+                while let Ok(chunk) = self.buf_pool_rx.try_recv() {
+                    self.shared.chunk_taken(self.chunk_count);
+                    spares.push(chunk);
+                }
+            }
+
+            spares.pop()
+        };
+
+        if let Some(chunk) = spare {
+            return Poll::Ready(Ok(chunk));
+        }
+
+        match Pin::new(&mut self.buf_pool_rx).poll_next(cx) {
+            // Wait for the reader to finish reading a chunk.
+            Poll::Pending => {
+                #[cfg(feature = "metrics")]
+                self.shared.metrics.writer_waits.fetch_add(1, Ordering::Relaxed);
+
+                Poll::Pending
+            }
+
+            // Pipe has closed.
+            Poll::Ready(None) => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+
+            // An available buffer has been found.
+            Poll::Ready(Some(chunk)) => {
+                self.shared.chunk_taken(self.chunk_count);
+                Poll::Ready(Ok(chunk))
+            }
+        }
+    }
+
+    /// Wait for an available buffer for the caller to fill in place.
+    pub(crate) fn poll_reserve(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Cursor<Vec<u8>>>> {
+        if self.is_closed() {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+
+        if let Some(limiter) = self.limiter.as_mut() {
+            ready!(limiter.poll_ready(&self.shared.rate_budget, cx));
+        }
+
+        if self.rendezvous {
+            ready!(self.poll_idle(cx))?;
+        }
+
+        self.poll_acquire_chunk(cx)
+    }
+
+    /// Send a chunk previously reserved and filled in by the caller.
+    pub(crate) fn commit(&mut self, chunk: Cursor<Vec<u8>>) -> io::Result<()> {
+        // Do not send empty buffers through the rotation.
+        if chunk.get_ref().is_empty() {
+            self.release(chunk);
+            return Ok(());
+        }
+
+        // Coalesced writes came first, so they must be sent first.
+        self.send_pending()?;
+
+        let len = chunk.get_ref().len();
+        self.send_chunk(chunk)?;
+        self.record_write(len);
+
+        Ok(())
+    }
+
+    /// Account for bytes accepted by a write.
+    fn record_write(&mut self, len: usize) {
+        self.position += len as u64;
+
+        if let Some(limiter) = self.limiter.as_ref() {
+            limiter.record(&self.shared.rate_budget, len);
+        }
+
+        #[cfg(feature = "metrics")]
+        self.shared.metrics.record_write(len);
+    }
+
+    /// Give back a reserved chunk without sending it.
+    pub(crate) fn release(&mut self, mut chunk: Cursor<Vec<u8>>) {
+        chunk.get_mut().clear();
+        self.shared.spares.lock().unwrap().push(chunk);
+    }
+
+    /// Write a buffer to the pipe, returning how many bytes were accepted.
+    fn poll_write_chunk(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
         // If the reading end of the pipe is closed then return an error now,
         // otherwise we'd be spending time writing the entire buffer only to
         // discover that it is closed afterward.
-        if self.buf_stream_tx.is_closed() {
+        if self.is_closed() {
             return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
         }
 
@@ -206,41 +1046,161 @@ impl AsyncWrite for Writer {
             return Poll::Ready(Ok(0));
         }
 
-        // Attempt to grab an available buffer to write the chunk to.
-        match Pin::new(&mut self.buf_pool_rx).poll_next(cx) {
-            // Wait for the reader to finish reading a chunk.
-            Poll::Pending => Poll::Pending,
+        // In rendezvous mode, nothing is sent until the reader has consumed
+        // everything and is waiting for more, so that the write completes
+        // exactly when it is handed off. Until then the write has no effect,
+        // so it can be cancelled safely.
+        if self.rendezvous {
+            ready!(self.poll_idle(cx))?;
+        }
 
-            // Pipe has closed.
-            Poll::Ready(None) => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+        // If we are coalescing writes, append to the chunk we are holding on
+        // to if the buffer fits.
+        if let Some(threshold) = self.coalesce_threshold {
+            if let Some(chunk) = self.pending.as_mut() {
+                if chunk.get_ref().len() + buf.len() <= threshold {
+                    chunk.get_mut().extend_from_slice(buf);
 
-            // An available buffer has been found.
-            Poll::Ready(Some(mut chunk)) => {
-                // Write the buffer to the chunk.
-                chunk.get_mut().extend_from_slice(buf);
-
-                // Send the chunk to the reader.
-                match self.buf_stream_tx.try_send(chunk) {
-                    Ok(()) => Poll::Ready(Ok(buf.len())),
-
-                    Err(e) => {
-                        if e.is_full() {
-                            panic!("buffer pool overflow")
-                        } else {
-                            Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()))
-                        }
+                    if chunk.get_ref().len() >= threshold {
+                        self.send_pending()?;
                     }
+
+                    return Poll::Ready(Ok(buf.len()));
                 }
+
+                // The buffer doesn't fit, so send what we have so far and
+                // start a new chunk.
+                self.send_pending()?;
+            }
+        }
+
+        // Attempt to grab an available buffer to write the chunk to.
+        let mut chunk = ready!(self.poll_acquire_chunk(cx))?;
+
+        // Write the buffer to the chunk.
+        chunk.get_mut().extend_from_slice(buf);
+
+        // Hold on to the chunk if there is still room to coalesce more writes
+        // into it, otherwise send it to the reader.
+        match self.coalesce_threshold {
+            Some(threshold) if chunk.get_ref().len() < threshold => {
+                self.pending = Some(chunk);
             }
+            _ => self.send_chunk(chunk)?,
         }
+
+        Poll::Ready(Ok(buf.len()))
     }
+}
 
-    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Poll::Ready(Ok(()))
+impl AsyncWrite for Writer {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+
+        if let Some(limiter) = this.limiter.as_mut() {
+            ready!(limiter.poll_ready(&this.shared.rate_budget, cx));
+        }
+
+        let amt = ready!(self.poll_write_chunk(cx, buf))?;
+
+        if amt > 0 {
+            self.record_write(amt);
+        }
+
+        Poll::Ready(Ok(amt))
     }
 
-    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
-        self.buf_stream_tx.close();
-        Poll::Ready(Ok(()))
+    fn poll_flush(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(self.send_pending())
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let result = self.send_pending();
+        self.close_stream();
+        Poll::Ready(result)
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        // Make sure coalesced writes are not lost if the writer is dropped
+        // without being flushed.
+        let _ = self.send_pending();
+
+        self.close_stream();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{
+        executor::block_on,
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt},
+    };
+
+    #[test]
+    fn pool_is_filled_with_initial_chunk_capacity() {
+        let builder = PipeBuilder::new().chunk_count(2).initial_chunk_capacity(1024);
+        let (_reader, writer) = new(&builder);
+
+        for _ in 0..2 {
+            let chunk = writer.buf_pool_rx.try_recv().unwrap();
+            assert!(chunk.get_ref().capacity() >= 1024);
+        }
+    }
+    #[test]
+    fn recycled_chunks_are_shrunk_to_max_retained_capacity() {
+        block_on(async {
+            let builder = PipeBuilder::new().chunk_count(1).max_chunk_retained_capacity(16);
+            let (mut reader, mut writer) = new(&builder);
+
+            writer.write_all(&[0xff; 100_000]).await.unwrap();
+
+            let mut buf = vec![0; 100_000];
+            reader.read_exact(&mut buf).await.unwrap();
+
+            let chunk = writer.buf_pool_rx.try_recv().unwrap();
+            assert!(chunk.get_ref().capacity() <= 16);
+        })
+    }
+    /// Write two chunks and read them back in order, then return the address
+    /// of each chunk's buffer and that of the buffer used for the next write.
+    fn chunk_reuse_order(lifo: bool) -> [*const u8; 3] {
+        block_on(async {
+            let builder = PipeBuilder::new().chunk_count(2).lifo_chunk_reuse(lifo);
+            let (mut reader, mut writer) = new(&builder);
+            let mut addrs = [std::ptr::null(); 3];
+
+            writer.write_all(b"a").await.unwrap();
+            writer.write_all(b"b").await.unwrap();
+
+            for addr in &mut addrs[..2] {
+                *addr = reader.fill_buf().await.unwrap().as_ptr();
+                reader.consume_unpin(1);
+            }
+            assert_ne!(addrs[0], addrs[1]);
+
+            writer.write_all(b"c").await.unwrap();
+            addrs[2] = reader.fill_buf().await.unwrap().as_ptr();
+
+            addrs
+        })
+    }
+
+    #[test]
+    fn fifo_chunk_reuse_hands_out_least_recently_returned_chunk() {
+        let [first, _, next] = chunk_reuse_order(false);
+        assert_eq!(next, first);
+    }
+
+    #[test]
+    fn lifo_chunk_reuse_hands_out_most_recently_returned_chunk() {
+        let [_, second, next] = chunk_reuse_order(true);
+        assert_eq!(next, second);
     }
 }
@@ -0,0 +1,141 @@
+//! Typed messages sent over length-delimited frames.
+//!
+//! A [`FramedPipe`] sends and receives values of any type supported by its
+//! codec. Each value is encoded into a single frame using the same framing as
+//! [`FramedWriter`], so values may be split across chunks in any way and are
+//! reassembled by the receiving side before being decoded.
+//!
+//! Serialization is left entirely to the codec. The default [`LengthCodec`]
+//! sends raw bytes, and other formats can be supported by implementing
+//! [`Encoder`] and [`Decoder`].
+
+use super::{
+    framed::{FramedReader, FramedWriter},
+    PipeReader, PipeWriter,
+};
+use std::{convert::TryFrom, io};
+
+/// Encodes values of type `T` into frames.
+pub trait Encoder<T: ?Sized> {
+    /// Encode the given item, appending its bytes to `dst`.
+    fn encode(&mut self, item: &T, dst: &mut Vec<u8>) -> io::Result<()>;
+}
+
+/// Decodes values of type `T` from frames.
+pub trait Decoder<T> {
+    /// Decode an item from a complete frame.
+    fn decode(&mut self, frame: &[u8]) -> io::Result<T>;
+}
+
+/// A codec that sends the bytes of each value as-is, relying on the framing
+/// for the length prefix.
+///
+/// Any type implementing `AsRef<[u8]>` can be sent, and any type implementing
+/// `TryFrom<&[u8]>` can be received. Frames that fail to convert produce an
+/// [`InvalidData`](io::ErrorKind::InvalidData) error.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LengthCodec;
+
+impl<T: AsRef<[u8]> + ?Sized> Encoder<T> for LengthCodec {
+    fn encode(&mut self, item: &T, dst: &mut Vec<u8>) -> io::Result<()> {
+        dst.extend_from_slice(item.as_ref());
+        Ok(())
+    }
+}
+
+impl<T> Decoder<T> for LengthCodec
+where
+    T: for<'a> TryFrom<&'a [u8]>,
+{
+    fn decode(&mut self, frame: &[u8]) -> io::Result<T> {
+        T::try_from(frame).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, "frame could not be decoded")
+        })
+    }
+}
+
+/// One end of a message channel, sending typed values over one pipe and
+/// receiving them from another.
+///
+/// This is meant for two-way communication: create two pipes, and give each
+/// end the reader of one and the writer of the other. For one-way messages
+/// between two tasks, use a [`FramedWriter`] and [`FramedReader`] on the two
+/// halves of a single pipe instead, encoding and decoding values with a codec
+/// directly. Wrapping both halves of a single pipe in a `FramedPipe` is also
+/// possible, but only receives back the values it sent itself.
+#[derive(Debug)]
+pub struct FramedPipe<C = LengthCodec> {
+    reader: FramedReader,
+    writer: FramedWriter,
+    codec: C,
+}
+
+impl<C> FramedPipe<C> {
+    /// Create a new message channel reading from and writing to the given
+    /// pipes, using the given codec.
+    pub fn new(reader: PipeReader, writer: PipeWriter, codec: C) -> Self {
+        Self {
+            reader: FramedReader::new(reader),
+            writer: FramedWriter::new(writer),
+            codec,
+        }
+    }
+
+    /// Set the maximum length of an encoded value, which applies to both
+    /// sending and receiving. Default is 8 MiB.
+    pub fn max_frame_len(mut self, len: usize) -> Self {
+        self.reader = self.reader.max_frame_len(len);
+        self.writer = self.writer.max_frame_len(len);
+        self
+    }
+
+    /// Get a reference to the codec.
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    /// Get a mutable reference to the codec.
+    pub fn codec_mut(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    /// Encode and send a value.
+    ///
+    /// Values that encode to more than the maximum frame length produce an
+    /// [`InvalidInput`](io::ErrorKind::InvalidInput) error without writing
+    /// anything.
+    pub async fn send<T>(&mut self, item: &T) -> io::Result<()>
+    where
+        T: ?Sized,
+        C: Encoder<T>,
+    {
+        let codec = &mut self.codec;
+        self.writer.send_with(|buf| codec.encode(item, buf)).await
+    }
+
+    /// Receive and decode the next value.
+    ///
+    /// Returns `None` if the pipe was closed cleanly between values. Errors are
+    /// returned as described in [`FramedReader::recv`], or by the codec if the
+    /// frame cannot be decoded.
+    pub async fn recv<T>(&mut self) -> io::Result<Option<T>>
+    where
+        C: Decoder<T>,
+    {
+        match self.reader.recv().await? {
+            Some(frame) => self.codec.decode(&frame).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Close the outgoing pipe, signaling to the other end that no more values
+    /// will be sent.
+    pub async fn close(&mut self) -> io::Result<()> {
+        self.writer.close().await
+    }
+
+    /// Get the underlying pipes.
+    pub fn into_inner(self) -> (PipeReader, PipeWriter) {
+        (self.reader.into_inner(), self.writer.into_inner())
+    }
+}
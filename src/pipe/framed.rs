@@ -0,0 +1,266 @@
+//! Length-delimited framing for sending discrete messages over a pipe.
+//!
+//! Each frame is written as a 4-byte big-endian length prefix followed by the
+//! frame payload. Frames may be split across chunks in any way; the reader
+//! reassembles them before returning them.
+
+use super::{PipeReader, PipeWriter};
+use futures_core::Stream;
+use futures_io::{AsyncBufRead, AsyncWrite};
+use std::{
+    future::poll_fn,
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+/// Default maximum frame length, which is 8 MiB.
+const DEFAULT_MAX_FRAME_LEN: usize = 8 * 1024 * 1024;
+
+/// Size of the length prefix of each frame.
+const HEADER_LEN: usize = 4;
+
+/// Reads length-delimited frames from a pipe.
+///
+/// Frames can be received one at a time with [`recv`](FramedReader::recv), or
+/// by reading from this reader as a [`Stream`] of frames. The stream ends after
+/// the first error.
+#[derive(Debug)]
+pub struct FramedReader {
+    inner: PipeReader,
+    max_frame_len: usize,
+
+    /// Length prefix of the next frame being read.
+    header: [u8; HEADER_LEN],
+
+    /// Number of bytes of the length prefix read so far.
+    header_len: usize,
+
+    /// Payload of the current frame being read, once its length is known.
+    frame: Option<Vec<u8>>,
+
+    /// Declared length of the current frame.
+    frame_len: usize,
+
+    /// Kind of the error that stopped the reader, if any.
+    failed: Option<io::ErrorKind>,
+}
+
+impl FramedReader {
+    /// Create a new framed reader reading from the given pipe.
+    pub fn new(reader: PipeReader) -> Self {
+        Self {
+            inner: reader,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            header: [0; HEADER_LEN],
+            header_len: 0,
+            frame: None,
+            frame_len: 0,
+            failed: None,
+        }
+    }
+
+    /// Set the maximum length of a frame that will be accepted. Default is 8
+    /// MiB.
+    ///
+    /// Frames declaring a longer length are rejected before any memory is
+    /// allocated for them, which prevents a misbehaving writer from causing
+    /// unbounded allocation.
+    pub fn max_frame_len(mut self, len: usize) -> Self {
+        self.max_frame_len = len;
+        self
+    }
+
+    /// Receive the next frame from the pipe.
+    ///
+    /// Returns `None` if the pipe was closed cleanly between frames. If the
+    /// pipe is closed partway through a frame, an
+    /// [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) error is returned
+    /// instead. Frames longer than the maximum frame length produce an
+    /// [`InvalidData`](io::ErrorKind::InvalidData) error.
+    ///
+    /// After an error, the position of the next frame in the pipe is unknown,
+    /// so every later call returns an error of the same kind instead of
+    /// reading garbage as a frame.
+    pub async fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// Attempt to receive the next frame from the pipe.
+    ///
+    /// See [`recv`](FramedReader::recv) for details.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Option<Vec<u8>>>> {
+        if let Some(kind) = self.failed {
+            return Poll::Ready(Err(io::Error::new(
+                kind,
+                "framed reader stopped by an earlier error",
+            )));
+        }
+
+        let result = ready!(self.poll_frame(cx));
+
+        if let Err(e) = &result {
+            self.failed = Some(e.kind());
+        }
+
+        Poll::Ready(result)
+    }
+
+    /// Read the next frame from the pipe, picking up where the last call left
+    /// off.
+    fn poll_frame(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Option<Vec<u8>>>> {
+        loop {
+            // Return the current frame once all of it has been read.
+            if let Some(frame) = self.frame.as_ref() {
+                if frame.len() == self.frame_len {
+                    return Poll::Ready(Ok(self.frame.take()));
+                }
+            }
+
+            let buf = ready!(Pin::new(&mut self.inner).poll_fill_buf(cx))?;
+
+            if buf.is_empty() {
+                // The pipe ended cleanly only if it was between frames.
+                return Poll::Ready(if self.header_len == 0 && self.frame.is_none() {
+                    Ok(None)
+                } else {
+                    self.header_len = 0;
+                    self.frame = None;
+                    Err(io::ErrorKind::UnexpectedEof.into())
+                });
+            }
+
+            let amt = match self.frame.as_mut() {
+                // Still reading the length prefix.
+                None => {
+                    let amt = buf.len().min(HEADER_LEN - self.header_len);
+                    self.header[self.header_len..self.header_len + amt]
+                        .copy_from_slice(&buf[..amt]);
+                    self.header_len += amt;
+                    amt
+                }
+
+                // Reading the payload.
+                Some(frame) => {
+                    let amt = buf.len().min(self.frame_len - frame.len());
+                    frame.extend_from_slice(&buf[..amt]);
+                    amt
+                }
+            };
+
+            Pin::new(&mut self.inner).consume(amt);
+
+            // Once the length prefix is complete, check it before allocating
+            // room for the payload.
+            if self.header_len == HEADER_LEN {
+                self.header_len = 0;
+                self.frame_len = u32::from_be_bytes(self.header) as usize;
+
+                if self.frame_len > self.max_frame_len {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "frame length exceeds maximum",
+                    )));
+                }
+
+                self.frame = Some(Vec::with_capacity(self.frame_len));
+            }
+        }
+    }
+
+    /// Get the underlying pipe reader.
+    pub fn into_inner(self) -> PipeReader {
+        self.inner
+    }
+}
+
+impl Stream for FramedReader {
+    type Item = io::Result<Vec<u8>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // The error that stopped the reader has already been yielded.
+        if self.failed.is_some() {
+            return Poll::Ready(None);
+        }
+
+        self.poll_recv(cx).map(Result::transpose)
+    }
+}
+
+/// Writes length-delimited frames to a pipe.
+#[derive(Debug)]
+pub struct FramedWriter {
+    inner: PipeWriter,
+    max_frame_len: usize,
+
+    /// Reusable buffer for assembling each frame so that it is written as a
+    /// single chunk.
+    buf: Vec<u8>,
+}
+
+impl FramedWriter {
+    /// Create a new framed writer writing to the given pipe.
+    pub fn new(writer: PipeWriter) -> Self {
+        Self {
+            inner: writer,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Set the maximum length of a frame that may be sent. Default is 8 MiB.
+    pub fn max_frame_len(mut self, len: usize) -> Self {
+        self.max_frame_len = len;
+        self
+    }
+
+    /// Send a frame to the reader.
+    ///
+    /// Frames longer than the maximum frame length produce an
+    /// [`InvalidInput`](io::ErrorKind::InvalidInput) error without writing
+    /// anything.
+    pub async fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.send_with(|buf| {
+            buf.extend_from_slice(frame);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Send a frame whose payload is appended to the given buffer by `encode`.
+    ///
+    /// The payload is written right after room reserved for the length
+    /// prefix, so it is only copied once more on its way into the pipe.
+    pub(crate) async fn send_with<F>(&mut self, encode: F) -> io::Result<()>
+    where
+        F: FnOnce(&mut Vec<u8>) -> io::Result<()>,
+    {
+        self.buf.clear();
+        self.buf.extend_from_slice(&[0; HEADER_LEN]);
+        encode(&mut self.buf)?;
+
+        let len = self.buf.len() - HEADER_LEN;
+
+        if len > self.max_frame_len || len > u32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame length exceeds maximum",
+            ));
+        }
+
+        self.buf[..HEADER_LEN].copy_from_slice(&(len as u32).to_be_bytes());
+
+        self.inner.write_buf(&self.buf).await
+    }
+
+    /// Close the pipe, signaling to the reader that no more frames will be
+    /// sent.
+    pub async fn close(&mut self) -> io::Result<()> {
+        poll_fn(|cx| Pin::new(&mut self.inner).poll_close(cx)).await
+    }
+
+    /// Get the underlying pipe writer.
+    pub fn into_inner(self) -> PipeWriter {
+        self.inner
+    }
+}
@@ -3,36 +3,758 @@
 //! Pipes are like byte-oriented channels that implement I/O traits for reading
 //! and writing.
 
+use futures_core::Stream;
 use futures_io::{AsyncBufRead, AsyncRead, AsyncWrite};
 use std::{
     fmt,
+    future::{poll_fn, Future},
     io,
+    io::Cursor,
+    ops::{Deref, DerefMut},
     pin::Pin,
-    task::{Context, Poll},
+    task::{ready, Context, Poll},
+    time::Duration,
 };
 
+mod blocking;
 mod chunked;
+pub mod codec;
+pub mod framed;
+mod object;
+mod rate;
+mod shared;
+mod take;
+
+pub use self::{
+    blocking::BlockingReader,
+    object::{ObjectReader, ObjectWriter},
+    shared::SharedReader,
+    take::Take,
+};
 
 /// How many chunks should be available in a chunked pipe. Default is 4, which
 /// strikes a good balance of low memory usage and throughput.
 const DEFAULT_CHUNK_COUNT: usize = 4;
 
+/// How many bytes to read at a time when writing from a synchronous reader.
+const READER_CHUNK_LEN: usize = 8 * 1024;
+
 /// Creates a new asynchronous pipe with the default configuration.
 ///
 /// The default implementation guarantees that when writing a slice of bytes,
 /// either the entire slice is written at once or not at all. Slices will never
 /// be partially written.
+///
+/// To create a pipe with a custom configuration, use [`PipeBuilder`] instead.
 pub fn pipe() -> (PipeReader, PipeWriter) {
-    let (reader, writer) = chunked::new(DEFAULT_CHUNK_COUNT);
+    PipeBuilder::new().build()
+}
+
+/// Creates a new asynchronous pipe with no buffering.
+///
+/// Every write to a rendezvous pipe is handed off directly to the reader, and
+/// the write does not complete until the reader has read everything written
+/// before it and is waiting for more. This gives synchronous channel semantics
+/// over bytes, which can be useful when the writer must not get ahead of the
+/// reader at all.
+///
+/// Like the default pipe, slices are always written entirely or not at all.
+/// Nothing is sent until the write completes, so a write that is cancelled
+/// while waiting for the reader has no effect.
+pub fn rendezvous() -> (PipeReader, PipeWriter) {
+    let mut builder = PipeBuilder::new().chunk_count(1);
+    builder.rendezvous = true;
+    builder.build()
+}
+
+/// Creates a new asynchronous pipe for sending typed objects instead of bytes.
+///
+/// Objects can be sent one at a time or in batches, and are received in order by
+/// reading from the [`ObjectReader`] as a stream. Like a byte pipe, only a fixed
+/// number of batches can be in flight at once, and the vectors holding each
+/// batch are reused instead of being allocated for every send.
+pub fn object_pipe<T>() -> (ObjectReader<T>, ObjectWriter<T>) {
+    object::new(DEFAULT_CHUNK_COUNT)
+}
+
+/// Copies bytes from a reader into a writer, passing each chunk through a
+/// transform on the way.
+///
+/// The transform is called with every chunk read and a buffer to append its
+/// output to, which is then written to the writer. The output buffer is reused
+/// between chunks, so no allocation is needed per chunk once it has grown large
+/// enough. This is typically used to connect two pipes with a processing stage
+/// in between.
+///
+/// Runs until the reader reaches the end, then closes the writer. An error
+/// from either side stops the pump and is returned, dropping both ends.
+pub async fn pump<R, W, F>(mut reader: R, mut writer: W, mut transform: F) -> io::Result<()>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+    F: FnMut(&[u8], &mut Vec<u8>),
+{
+    let mut output = Vec::new();
+    let mut written = 0;
+
+    poll_fn(|cx| loop {
+        // Finish writing the output of the last chunk first.
+        while written < output.len() {
+            match ready!(Pin::new(&mut writer).poll_write(cx, &output[written..]))? {
+                0 => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                amt => written += amt,
+            }
+        }
+
+        let chunk = ready!(Pin::new(&mut reader).poll_fill_buf(cx))?;
+
+        if chunk.is_empty() {
+            return Pin::new(&mut writer).poll_close(cx);
+        }
+
+        output.clear();
+        written = 0;
+        transform(chunk, &mut output);
+
+        let amt = chunk.len();
+        Pin::new(&mut reader).consume(amt);
+    })
+    .await
+}
+
+/// A builder for creating a pipe with a custom configuration.
+#[derive(Clone, Debug)]
+pub struct PipeBuilder {
+    chunk_count: usize,
+    initial_chunk_capacity: usize,
+    max_chunk_retained_capacity: Option<usize>,
+    coalesce_threshold: Option<usize>,
+    lifo_chunk_reuse: bool,
+    rate_limit: Option<rate::RateLimit>,
+    rendezvous: bool,
+}
+
+impl Default for PipeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PipeBuilder {
+    /// Create a new builder with the default configuration.
+    pub fn new() -> Self {
+        Self {
+            chunk_count: DEFAULT_CHUNK_COUNT,
+            initial_chunk_capacity: 0,
+            max_chunk_retained_capacity: None,
+            coalesce_threshold: None,
+            lifo_chunk_reuse: false,
+            rate_limit: None,
+            rendezvous: false,
+        }
+    }
+
+    /// Set how many chunks should be available in the pipe at once.
+    ///
+    /// Smaller values reduce overall memory usage, while larger values reduce
+    /// the amount of waiting done between chunks if the reader and writer run
+    /// at different speeds. Default is 4.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `count` is zero.
+    pub fn chunk_count(mut self, count: usize) -> Self {
+        assert!(count > 0, "chunk count must be greater than zero");
+        self.chunk_count = count;
+        self
+    }
+
+    /// Set the initial capacity in bytes to allocate for each chunk buffer.
+    ///
+    /// Chunk buffers grow as needed to fit each write, so this is only an
+    /// optimization. If you know roughly how large your writes will be, setting
+    /// this avoids reallocating the buffers while the pipe warms up. Default is
+    /// 0, which allocates nothing until the first write.
+    pub fn initial_chunk_capacity(mut self, capacity: usize) -> Self {
+        self.initial_chunk_capacity = capacity;
+        self
+    }
+
+    /// Set the maximum capacity in bytes that a chunk buffer may retain after
+    /// it has been read and returned to the writer for reuse.
+    ///
+    /// Chunk buffers grow to fit the largest write they have ever held, so a
+    /// single large write can otherwise keep a large allocation alive for the
+    /// lifetime of the pipe. When set, chunk buffers that have grown larger
+    /// than this are reallocated at this size before being reused. Default is
+    /// no limit.
+    pub fn max_chunk_retained_capacity(mut self, capacity: usize) -> Self {
+        self.max_chunk_retained_capacity = Some(capacity);
+        self
+    }
+
+    /// Combine small writes into a single chunk until it holds at least
+    /// `threshold` bytes.
+    ///
+    /// By default every write is sent to the reader as its own chunk, so a
+    /// writer making lots of tiny writes will frequently have to wait for the
+    /// reader to free up a chunk. With coalescing enabled, writes are instead
+    /// appended to a chunk held by the writer, which is only sent to the reader
+    /// once the next write would no longer fit, or when the writer is flushed or
+    /// closed.
+    ///
+    /// This trades latency for throughput: bytes written are not visible to
+    /// the reader until their chunk is sent, so writers using this should flush
+    /// whenever the reader needs to see everything written so far. A single
+    /// write is still never split across chunks, even if it is larger than the
+    /// threshold.
+    pub fn coalesce_writes(mut self, threshold: usize) -> Self {
+        self.coalesce_threshold = Some(threshold);
+        self
+    }
+
+    /// Reuse the most recently returned chunk buffer first, instead of the one
+    /// that was returned the longest time ago.
+    ///
+    /// By default chunk buffers are reused in the order the reader returns
+    /// them. A buffer that was just read from is likely still in the CPU cache,
+    /// so reusing it right away can be faster for a writer and reader that are
+    /// closely in step with each other. Default is off.
+    pub fn lifo_chunk_reuse(mut self, enabled: bool) -> Self {
+        self.lifo_chunk_reuse = enabled;
+        self
+    }
+
+    /// Limit how many bytes per second can be written into the pipe.
+    ///
+    /// Writes are paced so that the writer does not exceed the given rate on
+    /// average, which is useful for simulating a slow connection in tests or
+    /// keeping a background transfer from hogging resources. Since this crate
+    /// does not depend on any particular async runtime, `sleep` must be
+    /// provided to create a future that completes after the given duration,
+    /// such as a runtime's own timer. Clones of the writer all share the same
+    /// limit, so it applies to the pipe as a whole.
+    ///
+    /// The rate limit is not part of the exported [`PipeConfig`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bytes_per_sec` is zero.
+    pub fn rate_limit<F, S>(mut self, bytes_per_sec: u64, sleep: F) -> Self
+    where
+        F: Fn(Duration) -> S + Send + Sync + 'static,
+        S: Future<Output = ()> + Send + 'static,
+    {
+        assert!(bytes_per_sec > 0, "rate limit must be greater than zero");
+        self.rate_limit = Some(rate::RateLimit::new(bytes_per_sec, sleep));
+        self
+    }
+
+    /// Create a new builder from a previously exported configuration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configured chunk count is zero.
+    pub fn from_config(config: PipeConfig) -> Self {
+        let mut builder = Self::new()
+            .chunk_count(config.chunk_count)
+            .initial_chunk_capacity(config.initial_chunk_capacity);
+
+        builder.max_chunk_retained_capacity = config.max_chunk_retained_capacity;
+        builder.coalesce_threshold = config.coalesce_threshold;
+        builder.lifo_chunk_reuse = config.lifo_chunk_reuse;
+        builder.rendezvous = config.rendezvous;
+        builder
+    }
+
+    /// Export the configuration of this builder as a plain struct.
+    pub fn to_config(&self) -> PipeConfig {
+        PipeConfig {
+            chunk_count: self.chunk_count,
+            initial_chunk_capacity: self.initial_chunk_capacity,
+            max_chunk_retained_capacity: self.max_chunk_retained_capacity,
+            coalesce_threshold: self.coalesce_threshold,
+            lifo_chunk_reuse: self.lifo_chunk_reuse,
+            rendezvous: self.rendezvous,
+        }
+    }
+
+    /// Create a new pipe using this configuration.
+    pub fn build(&self) -> (PipeReader, PipeWriter) {
+        let (reader, writer) = chunked::new(self);
+
+        (PipeReader { inner: reader }, PipeWriter { inner: writer })
+    }
+}
+
+/// A plain representation of a pipe configuration.
+///
+/// This can be exported from a [`PipeBuilder`] and used to create an identical
+/// builder later. With the `serde` feature enabled, this can also be serialized
+/// and deserialized so that pipe configuration can be stored in a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct PipeConfig {
+    /// How many chunks should be available in the pipe at once.
+    pub chunk_count: usize,
+
+    /// The initial capacity in bytes to allocate for each chunk buffer.
+    pub initial_chunk_capacity: usize,
+
+    /// The maximum capacity in bytes that a chunk buffer may retain after it
+    /// has been read, if any.
+    pub max_chunk_retained_capacity: Option<usize>,
+
+    /// The size in bytes up to which small writes are combined into a single
+    /// chunk, if any.
+    pub coalesce_threshold: Option<usize>,
+
+    /// Whether the most recently returned chunk buffer is reused first.
+    pub lifo_chunk_reuse: bool,
 
-    (PipeReader { inner: reader }, PipeWriter { inner: writer })
+    /// Whether writes wait for the reader to consume them before completing.
+    pub rendezvous: bool,
+}
+
+impl Default for PipeConfig {
+    fn default() -> Self {
+        PipeBuilder::new().to_config()
+    }
+}
+
+/// Statistics about the sizes of writes and chunks in a pipe.
+///
+/// Comparing write sizes with chunk sizes shows how well writes are being
+/// coalesced, and can help with choosing a chunk count and initial chunk
+/// capacity. Sizes are in bytes, and are zero if nothing has been recorded yet.
+/// Returned by [`PipeWriter::stats`].
+#[cfg(feature = "metrics")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PipeStats {
+    /// Number of writes made to the pipe.
+    pub writes: u64,
+
+    /// Size of the smallest write.
+    pub min_write: u64,
+
+    /// Size of the largest write.
+    pub max_write: u64,
+
+    /// Average size of a write, rounded down.
+    pub avg_write: u64,
+
+    /// Number of chunks sent to the reader.
+    pub chunks: u64,
+
+    /// Size of the smallest chunk sent.
+    pub min_chunk: u64,
+
+    /// Size of the largest chunk sent.
+    pub max_chunk: u64,
+
+    /// Average size of a chunk sent, rounded down.
+    pub avg_chunk: u64,
 }
 
 /// The reading end of an asynchronous pipe.
+///
+/// Reading is cancellation safe: a chunk taken from the writer is held by the
+/// reader itself until it has been consumed, so dropping a pending read, for
+/// example when it loses a `select!`, never loses any bytes. Methods that read
+/// into a caller's buffer across several chunks, such as
+/// [`read_exact_counted`](PipeReader::read_exact_counted), may still have
+/// copied some bytes into that buffer before being cancelled.
 pub struct PipeReader {
     inner: chunked::Reader,
 }
 
+impl PipeReader {
+    /// Get the capacity of the pipe, as the number of chunks that can be
+    /// buffered at once.
+    ///
+    /// Since each chunk grows to fit the write it holds, this does not limit
+    /// how many bytes the pipe can hold, but it does limit how many writes can
+    /// be made before the writer must wait for the reader.
+    pub fn capacity(&self) -> usize {
+        self.inner.chunk_count()
+    }
+
+    /// Check that none of the pipe's chunk buffers have been lost or
+    /// duplicated, panicking otherwise.
+    ///
+    /// This is a debugging aid for testing changes to the pipe itself, and is
+    /// only meaningful while the writer exists and neither half is in the
+    /// middle of an operation.
+    #[cfg(feature = "chunk-accounting")]
+    #[doc(hidden)]
+    pub fn assert_chunk_accounting(&self) {
+        self.inner.assert_chunk_accounting();
+    }
+
+    /// Get how full the pipe is, from 0.0 when empty to 1.0 when full.
+    ///
+    /// This is the fraction of the pipe's [`capacity`](PipeReader::capacity)
+    /// taken up by chunks that have not been read completely yet. Since chunks
+    /// vary in size, this says nothing about how many bytes are buffered. When
+    /// the pipe is full, the writer must wait for the reader before writing
+    /// more.
+    pub fn fill_ratio(&self) -> f32 {
+        let ratio = self.inner.unread_chunks() as f32 / self.inner.chunk_count() as f32;
+        ratio.clamp(0.0, 1.0)
+    }
+
+    /// Check if the [`fill_ratio`](PipeReader::fill_ratio) of the pipe is at
+    /// least the given threshold.
+    pub fn is_nearly_full(&self, threshold: f32) -> bool {
+        self.fill_ratio() >= threshold
+    }
+
+    /// Get the total number of bytes read from the pipe so far.
+    ///
+    /// This only ever increases, and counts bytes consumed through any means
+    /// of reading, including [`AsyncBufRead::consume`].
+    pub fn position(&self) -> u64 {
+        self.inner.position()
+    }
+
+    /// Get the total number of bytes read from the pipe so far.
+    #[cfg(feature = "metrics")]
+    pub fn bytes_read(&self) -> u64 {
+        self.inner.metrics().bytes_read()
+    }
+
+    /// Get the number of times the reader has had to wait for the writer.
+    ///
+    /// A high number compared to the number of chunks written means that the
+    /// reader is faster than the writer.
+    #[cfg(feature = "metrics")]
+    pub fn wait_count(&self) -> u64 {
+        self.inner.metrics().reader_waits()
+    }
+
+    /// Wait until the writer has closed the pipe and every byte written to it
+    /// has been read.
+    ///
+    /// This can be used to wait for the end of the pipe alongside other work
+    /// without having to poll a read. Note that the pipe cannot be read from
+    /// while waiting, so if bytes are still buffered when the writer closes the
+    /// pipe, this will not complete until they are read.
+    pub async fn eof(&self) {
+        poll_fn(|cx| self.inner.poll_eof(cx)).await
+    }
+
+    /// Take every byte that can be read from the pipe right now without
+    /// waiting, and return them as a single vector.
+    ///
+    /// This is useful for bailing out of a streaming read and collecting the
+    /// rest of the data at once. The result is only the final contents of the
+    /// pipe if the writer has already been closed; otherwise the writer may
+    /// still send more data after this returns.
+    pub fn drain_remaining(&mut self) -> Vec<u8> {
+        self.inner.drain_remaining()
+    }
+
+    /// Return this pipe to a fresh, empty state so that it can be reused,
+    /// given the writer for the same pipe.
+    ///
+    /// The chunk buffers already allocated by the pipe are kept, which makes
+    /// this useful for pooling pipes instead of creating a new one for every
+    /// use. Reset is meant to be used once the pipe is done with, and any bytes
+    /// still in the pipe are discarded. Both halves start over as if they were
+    /// just created, even if the pipe had been closed.
+    ///
+    /// Returns an [`InvalidInput`](io::ErrorKind::InvalidInput) error if the
+    /// writer belongs to a different pipe, or if the writer has been cloned.
+    pub fn reset(&mut self, writer: &mut PipeWriter) -> io::Result<()> {
+        chunked::reset(&mut self.inner, &mut writer.inner)
+    }
+
+    /// Create a reader that reads at most `limit` bytes from this pipe.
+    ///
+    /// The returned reader reports the end of the stream once the limit is
+    /// reached, including through [`AsyncBufRead`], which never exposes bytes
+    /// past the limit. Unlike a generic `take` adapter, the rest of the pipe is
+    /// left intact and can be read by recovering this reader with
+    /// [`Take::into_inner`]. This is useful for reading one message at a time
+    /// from a pipe carrying several messages of known length.
+    pub fn take(self, limit: u64) -> Take {
+        Take::new(self, limit)
+    }
+
+    /// Attempt to read bytes from the pipe into the spare capacity of the given
+    /// vector, returning how many bytes were appended.
+    ///
+    /// Unlike [`poll_read`](AsyncRead::poll_read), the destination does not
+    /// need to be initialized first, which saves having to zero a large buffer
+    /// before every read. At most `buf.capacity() - buf.len()` bytes are read,
+    /// so the vector never reallocates; reserve space in it beforehand. If there
+    /// is no spare capacity, `Ok(0)` is returned immediately.
+    pub fn poll_read_buf(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut Vec<u8>,
+    ) -> Poll<io::Result<usize>> {
+        let mut total = 0;
+
+        loop {
+            let spare = buf.capacity() - buf.len();
+
+            // Once we've read something, only keep going if more bytes are
+            // available right now.
+            if spare == 0 || (total > 0 && !self.inner.has_chunk_ready()) {
+                break;
+            }
+
+            match Pin::new(&mut self.inner).poll_fill_buf(cx)? {
+                Poll::Pending if total == 0 => return Poll::Pending,
+                Poll::Pending | Poll::Ready([]) => break,
+                Poll::Ready(chunk) => {
+                    let amt = chunk.len().min(spare);
+                    buf.extend_from_slice(&chunk[..amt]);
+                    Pin::new(&mut self.inner).consume(amt);
+                    total += amt;
+                }
+            }
+        }
+
+        Poll::Ready(Ok(total))
+    }
+
+    /// Read bytes from the pipe into the spare capacity of the given vector,
+    /// returning how many bytes were appended.
+    ///
+    /// See [`poll_read_buf`](PipeReader::poll_read_buf) for details.
+    pub async fn read_buf(&mut self, buf: &mut Vec<u8>) -> io::Result<usize> {
+        poll_fn(|cx| self.poll_read_buf(cx, buf)).await
+    }
+
+    /// Read the pipe as a stream of length-delimited frames, as written by a
+    /// [`FramedWriter`](framed::FramedWriter).
+    ///
+    /// Each item is the payload of one frame, reassembled if it was split
+    /// across several chunks. An error is produced if a frame declares a length
+    /// larger than `max_len`, without allocating room for it, or if the pipe
+    /// ends partway through a frame. The stream ends after the first error.
+    pub fn frames(self, max_len: usize) -> framed::FramedReader {
+        framed::FramedReader::new(self).max_frame_len(max_len)
+    }
+
+    /// Copy all remaining bytes in the pipe into the given writer until the
+    /// pipe is closed, returning the total number of bytes copied.
+    ///
+    /// Each chunk is written directly from the pipe's own buffers without being
+    /// copied into an intermediate buffer first, which makes this the fastest
+    /// way of draining a pipe into a writer. The writer is flushed once the end
+    /// of the pipe is reached.
+    pub async fn copy_into_buf<W>(mut self, mut writer: W) -> io::Result<u64>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut total = 0;
+
+        poll_fn(|cx| loop {
+            let chunk = ready!(Pin::new(&mut self.inner).poll_fill_buf(cx))?;
+
+            if chunk.is_empty() {
+                ready!(Pin::new(&mut writer).poll_flush(cx))?;
+                return Poll::Ready(Ok(total));
+            }
+
+            let amt = ready!(Pin::new(&mut writer).poll_write(cx, chunk))?;
+
+            if amt == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+
+            Pin::new(&mut self.inner).consume(amt);
+            total += amt as u64;
+        })
+        .await
+    }
+
+    /// Wait until at least `n` bytes can be read from the pipe at once, or the
+    /// pipe is closed.
+    ///
+    /// Once this returns `Poll::Ready(Ok(()))`, the next call to
+    /// [`poll_fill_buf`](AsyncBufRead::poll_fill_buf) returns a single
+    /// contiguous buffer of at least `n` bytes, unless the pipe was closed with
+    /// fewer remaining. This lets a decoder wait for exactly as much data as
+    /// it needs, such as a complete header, before reading it in one go.
+    ///
+    /// Chunks arriving in the meantime are combined into one, so `n` may be
+    /// larger than any single write and the writer is not held up waiting for
+    /// chunks to be consumed.
+    pub fn poll_fill_at_least(&mut self, cx: &mut Context<'_>, n: usize) -> Poll<io::Result<()>> {
+        self.inner.poll_fill_at_least(cx, n)
+    }
+
+    /// Read and discard everything left in the pipe until it is closed,
+    /// returning how many bytes were discarded.
+    ///
+    /// Chunks are consumed as they arrive without being copied anywhere, which
+    /// makes this a cheap way of fully consuming a stream whose contents are
+    /// not needed.
+    pub async fn drain(&mut self) -> io::Result<u64> {
+        let mut total = 0;
+
+        poll_fn(|cx| loop {
+            let amt = ready!(Pin::new(&mut self.inner).poll_fill_buf(cx))?.len();
+
+            if amt == 0 {
+                return Poll::Ready(Ok(total));
+            }
+
+            Pin::new(&mut self.inner).consume(amt);
+            total += amt as u64;
+        })
+        .await
+    }
+
+    /// Read exactly enough bytes to fill the given buffer, reporting how many
+    /// bytes were read if the pipe ends first.
+    ///
+    /// This behaves like `read_exact`, except that on failure the number of
+    /// bytes already read into the start of `buf` is returned along with the
+    /// error. If the pipe is closed before the buffer is filled, the error is
+    /// [`UnexpectedEof`](io::ErrorKind::UnexpectedEof).
+    pub async fn read_exact_counted(&mut self, buf: &mut [u8]) -> Result<(), (usize, io::Error)> {
+        let mut filled = 0;
+
+        poll_fn(|cx| {
+            while filled < buf.len() {
+                match ready!(Pin::new(&mut self.inner).poll_read(cx, &mut buf[filled..])) {
+                    Ok(0) => return Poll::Ready(Err((filled, io::ErrorKind::UnexpectedEof.into()))),
+                    Ok(amt) => filled += amt,
+                    Err(e) => return Poll::Ready(Err((filled, e))),
+                }
+            }
+
+            Poll::Ready(Ok(()))
+        })
+        .await
+    }
+
+    /// Read a little-endian `u16` from the pipe.
+    pub async fn read_u16_le(&mut self) -> io::Result<u16> {
+        self.read_array().await.map(u16::from_le_bytes)
+    }
+
+    /// Read a big-endian `u16` from the pipe.
+    pub async fn read_u16_be(&mut self) -> io::Result<u16> {
+        self.read_array().await.map(u16::from_be_bytes)
+    }
+
+    /// Read a little-endian `u32` from the pipe.
+    pub async fn read_u32_le(&mut self) -> io::Result<u32> {
+        self.read_array().await.map(u32::from_le_bytes)
+    }
+
+    /// Read a big-endian `u32` from the pipe.
+    pub async fn read_u32_be(&mut self) -> io::Result<u32> {
+        self.read_array().await.map(u32::from_be_bytes)
+    }
+
+    /// Read a little-endian `u64` from the pipe.
+    pub async fn read_u64_le(&mut self) -> io::Result<u64> {
+        self.read_array().await.map(u64::from_le_bytes)
+    }
+
+    /// Read a big-endian `u64` from the pipe.
+    pub async fn read_u64_be(&mut self) -> io::Result<u64> {
+        self.read_array().await.map(u64::from_be_bytes)
+    }
+
+    /// Copy at most `limit` bytes from the pipe into the given writer,
+    /// returning the number of bytes copied.
+    ///
+    /// Fewer bytes are copied only if the pipe is closed first. Like
+    /// [`copy_into_buf`](PipeReader::copy_into_buf), chunks are written directly
+    /// from the pipe's own buffers, and the writer is flushed before returning.
+    /// Any bytes past the limit are left in the pipe to be read later, even if
+    /// they are part of the same chunk.
+    pub async fn copy_into_limited<W>(&mut self, writer: &mut W, limit: u64) -> io::Result<u64>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut total = 0;
+
+        poll_fn(|cx| loop {
+            let chunk = if total < limit {
+                ready!(Pin::new(&mut self.inner).poll_fill_buf(cx))?
+            } else {
+                &[]
+            };
+
+            if chunk.is_empty() {
+                ready!(Pin::new(&mut *writer).poll_flush(cx))?;
+                return Poll::Ready(Ok(total));
+            }
+
+            let max = (chunk.len() as u64).min(limit - total) as usize;
+            let amt = ready!(Pin::new(&mut *writer).poll_write(cx, &chunk[..max]))?;
+
+            if amt == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+
+            Pin::new(&mut self.inner).consume(amt);
+            total += amt as u64;
+        })
+        .await
+    }
+
+    /// Read all bytes until the pipe is closed, appending them to `buf` and
+    /// returning the number of bytes read, but never reading more than `max`
+    /// bytes.
+    ///
+    /// If the pipe holds more than `max` bytes, an
+    /// [`InvalidData`](io::ErrorKind::InvalidData) error is returned once the
+    /// limit is reached. The first `max` bytes are still appended to `buf`, and
+    /// the rest are left in the pipe. This makes it safe to read the entire
+    /// contents of a pipe fed by an untrusted writer, since `buf` never grows by
+    /// more than `max` bytes.
+    pub async fn read_to_end_bounded(
+        &mut self,
+        buf: &mut Vec<u8>,
+        max: usize,
+    ) -> io::Result<usize> {
+        let mut total = 0;
+
+        poll_fn(|cx| loop {
+            let chunk = ready!(Pin::new(&mut self.inner).poll_fill_buf(cx))?;
+
+            if chunk.is_empty() {
+                return Poll::Ready(Ok(total));
+            }
+
+            // More data is available after reaching the limit.
+            if total == max {
+                return Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "pipe contents exceed maximum length",
+                )));
+            }
+
+            let amt = chunk.len().min(max - total);
+            buf.extend_from_slice(&chunk[..amt]);
+
+            Pin::new(&mut self.inner).consume(amt);
+            total += amt;
+        })
+        .await
+    }
+}
+
+impl PipeReader {
+    /// Read exactly `N` bytes into an array, even if they span several chunks.
+    async fn read_array<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        self.read_exact_counted(&mut bytes).await.map_err(|(_, e)| e)?;
+        Ok(bytes)
+    }
+}
+
 impl AsyncRead for PipeReader {
     fn poll_read(
         mut self: Pin<&mut Self>,
@@ -56,15 +778,274 @@ impl AsyncBufRead for PipeReader {
 
 impl fmt::Debug for PipeReader {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.pad("PipeReader")
+        f.debug_struct("PipeReader")
+            .field("closed", &self.inner.is_closed())
+            .field("buffered", &self.inner.buffered_len())
+            .finish()
     }
 }
 
 /// The writing end of an asynchronous pipe.
+///
+/// A writer can be cloned to let several tasks write into the same pipe. Each
+/// chunk is delivered whole, so writes from different clones are interleaved
+/// at chunk granularity: the bytes of a single `write` call are never split up
+/// by another clone, but larger writes made with `write_all` may be. The pipe
+/// is closed once every clone has been closed or dropped.
+///
+/// Methods that wait for the reader to consume every chunk, such as
+/// [`all_consumed`](PipeWriter::all_consumed), account for chunks sent by any
+/// clone, and will not complete while another clone is holding on to
+/// coalesced writes that have not been flushed.
+#[derive(Clone)]
 pub struct PipeWriter {
     inner: chunked::Writer,
 }
 
+impl PipeWriter {
+    /// Get the capacity of the pipe, as the number of chunks that can be
+    /// buffered at once.
+    ///
+    /// See [`PipeReader::capacity`] for details.
+    pub fn capacity(&self) -> usize {
+        self.inner.chunk_count()
+    }
+
+    /// Get the number of chunks that can be written right now without waiting
+    /// for the reader.
+    ///
+    /// If this is consistently 0, the writer is being held back by the reader
+    /// and a larger [`capacity`](PipeWriter::capacity) might help. If it is
+    /// consistently equal to the capacity, the reader is keeping up and is
+    /// waiting for the writer instead.
+    pub fn available_chunks(&self) -> usize {
+        self.inner.available_chunks()
+    }
+
+    /// Get the total number of bytes accepted by this writer so far.
+    ///
+    /// This only ever increases, and is cheap enough to check after every
+    /// write. Comparing it against [`PipeReader::position`] tells how far the
+    /// reader is lagging behind. A cloned writer counts its own bytes starting
+    /// from zero.
+    pub fn position(&self) -> u64 {
+        self.inner.position()
+    }
+
+    /// Get the total number of bytes sent to the reader so far.
+    ///
+    /// Bytes held back by write coalescing are not counted until they are
+    /// sent.
+    #[cfg(feature = "metrics")]
+    pub fn bytes_written(&self) -> u64 {
+        self.inner.metrics().bytes_written()
+    }
+
+    /// Get the total number of chunks sent to the reader so far.
+    #[cfg(feature = "metrics")]
+    pub fn chunks_written(&self) -> u64 {
+        self.inner.metrics().chunks_written()
+    }
+
+    /// Get statistics about the sizes of writes and chunks in the pipe, for
+    /// tuning its configuration.
+    #[cfg(feature = "metrics")]
+    pub fn stats(&self) -> PipeStats {
+        self.inner.metrics().stats()
+    }
+
+    /// Get the number of times the writer has had to wait for the reader to
+    /// free up a chunk.
+    ///
+    /// A high number compared to the number of chunks written means that the
+    /// writer is faster than the reader.
+    #[cfg(feature = "metrics")]
+    pub fn wait_count(&self) -> u64 {
+        self.inner.metrics().writer_waits()
+    }
+
+    /// Check whether the pipe can accept another chunk without waiting.
+    ///
+    /// Returns `Poll::Ready(Ok(()))` once a chunk buffer is available, in which
+    /// case the next write will not wait for the reader. Otherwise returns
+    /// `Poll::Pending` and schedules the current task to be woken when a buffer
+    /// becomes available. This lets a producer hold off on generating data
+    /// that cannot be written yet, in the same way as `Sink::poll_ready`.
+    ///
+    /// Returns a [`BrokenPipe`](io::ErrorKind::BrokenPipe) error if the pipe
+    /// has been closed or the reader has been dropped.
+    pub fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.inner.poll_ready(cx)
+    }
+
+    /// Wait until the reader has been dropped.
+    ///
+    /// This can be used to stop producing data as soon as nobody is around to
+    /// read it anymore, without having to poll a write.
+    pub async fn closed(&self) {
+        poll_fn(|cx| self.inner.poll_reader_dropped(cx)).await
+    }
+
+    /// Write every item produced by a stream of byte buffers into the pipe,
+    /// then close the pipe once the stream ends.
+    ///
+    /// This is a convenient way of feeding a pipe from another source of bytes,
+    /// such as a response body. If the stream produces an error, it is returned
+    /// immediately and the pipe is left open so that the caller can decide how
+    /// to handle it.
+    pub async fn send_all<S, B>(&mut self, stream: S) -> io::Result<()>
+    where
+        S: Stream<Item = io::Result<B>>,
+        B: AsRef<[u8]>,
+    {
+        let mut stream = Box::pin(stream);
+
+        while let Some(item) = poll_fn(|cx| stream.as_mut().poll_next(cx)).await {
+            self.write_buf(item?.as_ref()).await?;
+        }
+
+        poll_fn(|cx| Pin::new(&mut self.inner).poll_close(cx)).await
+    }
+
+    /// Reserve room for a chunk of `len` bytes that can be written to in
+    /// place.
+    ///
+    /// Waits until a chunk buffer is available, then returns a guard giving
+    /// direct access to `len` zeroed bytes of it. This allows a serializer to
+    /// render straight into the pipe instead of into a buffer of its own that
+    /// would then have to be copied. Nothing is sent to the reader until the
+    /// guard is committed with [`ChunkGuard::commit`]; dropping the guard
+    /// instead discards the chunk.
+    ///
+    /// Returns a [`BrokenPipe`](io::ErrorKind::BrokenPipe) error if the pipe
+    /// has been closed or the reader has been dropped.
+    pub async fn reserve(&mut self, len: usize) -> io::Result<ChunkGuard<'_>> {
+        let mut chunk = poll_fn(|cx| self.inner.poll_reserve(cx)).await?;
+        chunk.get_mut().resize(len, 0);
+
+        Ok(ChunkGuard {
+            writer: self,
+            chunk: Some(chunk),
+        })
+    }
+
+    /// Copy everything from a synchronous reader into the pipe until the
+    /// reader reaches its end, returning the number of bytes copied.
+    ///
+    /// Bytes are read directly into the pipe's chunk buffers, up to 8 KiB at a
+    /// time, so no intermediate buffer is needed. Reading blocks the current
+    /// thread if the reader blocks, while writing waits for room in the pipe
+    /// as usual. Reads that are [`Interrupted`](io::ErrorKind::Interrupted)
+    /// are retried, and any other error from the reader is returned. The pipe
+    /// is not closed afterward.
+    pub async fn write_from_reader<R>(&mut self, reader: &mut R) -> io::Result<u64>
+    where
+        R: io::Read + ?Sized,
+    {
+        let mut total = 0;
+
+        loop {
+            let mut chunk = self.reserve(READER_CHUNK_LEN).await?;
+
+            let amt = loop {
+                match reader.read(&mut chunk) {
+                    Ok(amt) => break amt,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                }
+            };
+
+            if amt == 0 {
+                return Ok(total);
+            }
+
+            chunk.commit(amt).await?;
+            total += amt as u64;
+        }
+    }
+
+    /// Write an entire buffer into the pipe, reporting how much of it was
+    /// written if an error occurs partway through.
+    ///
+    /// This behaves like `write_all`, except that on failure the number of
+    /// bytes from the start of `buf` that were accepted by the pipe before the
+    /// error is returned along with it. A producer that can resume elsewhere
+    /// can use this to know where to continue from, such as after a
+    /// [`BrokenPipe`](io::ErrorKind::BrokenPipe) error caused by the reader
+    /// being dropped.
+    ///
+    /// A buffer is normally accepted by the pipe as a single chunk, so an error
+    /// usually means that none of it was written.
+    pub async fn write_all_counted(&mut self, buf: &[u8]) -> Result<(), (usize, io::Error)> {
+        let mut written = 0;
+
+        poll_fn(|cx| {
+            while written < buf.len() {
+                match ready!(Pin::new(&mut self.inner).poll_write(cx, &buf[written..])) {
+                    Ok(0) => return Poll::Ready(Err((written, io::ErrorKind::WriteZero.into()))),
+                    Ok(amt) => written += amt,
+                    Err(e) => return Poll::Ready(Err((written, e))),
+                }
+            }
+
+            Poll::Ready(Ok(()))
+        })
+        .await
+    }
+
+    /// Close the pipe and wait until the reader has read to the end of it.
+    ///
+    /// This is stronger than waiting for the reader to consume everything, as
+    /// it also confirms that the reader has seen the end of the pipe. This is
+    /// useful for protocols that need a clean shutdown handshake, where the
+    /// writer must know that the reader finished before tearing down.
+    ///
+    /// Returns a [`BrokenPipe`](io::ErrorKind::BrokenPipe) error if the reader
+    /// is dropped before reaching the end of the pipe.
+    pub async fn close_and_wait(mut self) -> io::Result<()> {
+        poll_fn(|cx| Pin::new(&mut self.inner).poll_close(cx)).await?;
+        poll_fn(|cx| self.inner.poll_eof_observed(cx)).await
+    }
+
+    /// Wait until the reader has consumed every byte written so far.
+    ///
+    /// Unlike flushing, which only ensures that written bytes are available to
+    /// the reader, this waits until the reader has actually read all of them,
+    /// including any chunk it is currently partway through. This can be used as
+    /// a synchronization barrier between the writer and the reader.
+    ///
+    /// Since this borrows the writer mutably, no other writes can be issued
+    /// until it completes, so only bytes written before the call are waited
+    /// on.
+    ///
+    /// Returns a [`BrokenPipe`](io::ErrorKind::BrokenPipe) error if the reader
+    /// is dropped before consuming everything.
+    pub async fn all_consumed(&mut self) -> io::Result<()> {
+        poll_fn(|cx| self.inner.poll_all_consumed(cx)).await
+    }
+
+    /// Wait until the reader is idle.
+    ///
+    /// The reader is considered idle once it has consumed every byte written so
+    /// far (as with [`all_consumed`](PipeWriter::all_consumed)) and is also
+    /// actively waiting for more bytes to arrive. At this point the reader and
+    /// writer are fully synchronized, which makes it a good moment to do things
+    /// like take a consistent snapshot.
+    ///
+    /// Returns a [`BrokenPipe`](io::ErrorKind::BrokenPipe) error if the reader
+    /// is dropped.
+    pub async fn wait_idle(&mut self) -> io::Result<()> {
+        poll_fn(|cx| self.inner.poll_idle(cx)).await
+    }
+}
+
+impl PipeWriter {
+    /// Write an entire buffer into the pipe.
+    async fn write_buf(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.write_all_counted(buf).await.map_err(|(_, e)| e)
+    }
+}
+
 impl AsyncWrite for PipeWriter {
     fn poll_write(
         mut self: Pin<&mut Self>,
@@ -85,6 +1066,66 @@ impl AsyncWrite for PipeWriter {
 
 impl fmt::Debug for PipeWriter {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.pad("PipeWriter")
+        f.debug_struct("PipeWriter")
+            .field("closed", &self.inner.is_closed())
+            .field("buffered", &self.inner.buffered_len())
+            .finish()
+    }
+}
+
+/// A chunk reserved with [`PipeWriter::reserve`] that can be written to in
+/// place before it is sent.
+///
+/// The guard dereferences to the reserved bytes.
+pub struct ChunkGuard<'a> {
+    writer: &'a mut PipeWriter,
+    chunk: Option<Cursor<Vec<u8>>>,
+}
+
+impl ChunkGuard<'_> {
+    /// Send the first `len` bytes of the chunk to the reader, discarding the
+    /// rest.
+    ///
+    /// This allows reserving more room than ends up being needed. In a
+    /// rendezvous pipe, the reservation itself waits for the reader to be
+    /// ready, so the chunk is handed off as soon as it is committed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is larger than the reserved length.
+    pub async fn commit(mut self, len: usize) -> io::Result<()> {
+        let mut chunk = self.chunk.take().unwrap();
+        assert!(len <= chunk.get_ref().len(), "committed length exceeds reserved length");
+        chunk.get_mut().truncate(len);
+
+        self.writer.inner.commit(chunk)
+    }
+}
+
+impl Deref for ChunkGuard<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.chunk.as_ref().unwrap().get_ref()
+    }
+}
+
+impl DerefMut for ChunkGuard<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.chunk.as_mut().unwrap().get_mut()
+    }
+}
+
+impl Drop for ChunkGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(chunk) = self.chunk.take() {
+            self.writer.inner.release(chunk);
+        }
+    }
+}
+
+impl fmt::Debug for ChunkGuard<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChunkGuard").field("len", &self.len()).finish()
     }
 }
@@ -0,0 +1,188 @@
+//! A variation of the chunked pipe that transfers typed objects instead of
+//! bytes.
+//!
+//! Objects are sent in batches using a fixed number of reusable vectors that are
+//! exchanged back and forth between the writer and the reader, just like the
+//! chunks in a byte pipe. This bounds how many batches can be in flight at once,
+//! and avoids allocating a new vector for every batch sent.
+
+use async_channel::{bounded, Receiver, Sender};
+use futures_core::Stream;
+use std::{
+    fmt,
+    future::poll_fn,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Create a new object pipe with room for a fixed number of batches.
+pub(crate) fn new<T>(count: usize) -> (ObjectReader<T>, ObjectWriter<T>) {
+    let (buf_pool_tx, buf_pool_rx) = bounded(count);
+    let (buf_stream_tx, buf_stream_rx) = bounded(count);
+
+    // Fill up the buffer pool.
+    for _ in 0..count {
+        buf_pool_tx
+            .try_send(Vec::new())
+            .expect("buffer pool overflow");
+    }
+
+    let reader = ObjectReader {
+        buf_pool_tx,
+        buf_stream_rx,
+        batch: None,
+    };
+
+    let writer = ObjectWriter {
+        buf_pool_rx,
+        buf_stream_tx,
+    };
+
+    (reader, writer)
+}
+
+/// The reading end of an object pipe.
+///
+/// Objects are received in the same order they were sent by reading from this
+/// end as a [`Stream`]. The stream ends once the writer is closed or dropped
+/// and every object sent has been received.
+pub struct ObjectReader<T> {
+    /// A channel of batch buffers that have been consumed and can be reused.
+    buf_pool_tx: Sender<Vec<T>>,
+
+    /// A channel of incoming batches from the writer.
+    buf_stream_rx: Receiver<Vec<T>>,
+
+    /// The batch currently being read from, stored in reverse order so that
+    /// objects can be popped off of the end.
+    batch: Option<Vec<T>>,
+}
+
+// Objects are never pinned in place, so the reader can be moved freely
+// regardless of the object type.
+impl<T> Unpin for ObjectReader<T> {}
+
+impl<T> Stream for ObjectReader<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        loop {
+            if let Some(batch) = self.batch.as_mut() {
+                if let Some(item) = batch.pop() {
+                    return Poll::Ready(Some(item));
+                }
+
+                // The current batch is consumed, so return it to the writer
+                // for reuse. If the writer is gone then we just discard it.
+                let batch = self.batch.take().unwrap();
+                let _ = self.buf_pool_tx.try_send(batch);
+            }
+
+            match Pin::new(&mut self.buf_stream_rx).poll_next(cx) {
+                // Wait for a new batch to be delivered.
+                Poll::Pending => return Poll::Pending,
+
+                // Pipe has closed.
+                Poll::Ready(None) => return Poll::Ready(None),
+
+                // Accept the new batch.
+                Poll::Ready(Some(mut batch)) => {
+                    batch.reverse();
+                    self.batch = Some(batch);
+                }
+            }
+        }
+    }
+}
+
+impl<T> Drop for ObjectReader<T> {
+    fn drop(&mut self) {
+        // Close the primary stream first so that the writer knows the pipe is
+        // closed before trying to poll the pool channel.
+        self.buf_stream_rx.close();
+        self.buf_pool_tx.close();
+    }
+}
+
+impl<T> fmt::Debug for ObjectReader<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("ObjectReader")
+    }
+}
+
+/// The writing end of an object pipe.
+pub struct ObjectWriter<T> {
+    /// A channel of batch buffers available to write to.
+    buf_pool_rx: Receiver<Vec<T>>,
+
+    /// A channel of batches to send to the reader.
+    buf_stream_tx: Sender<Vec<T>>,
+}
+
+impl<T> ObjectWriter<T> {
+    /// Send a single object to the reader.
+    ///
+    /// Waits for a batch buffer to become available if the reader has fallen
+    /// behind. Returns a [`BrokenPipe`](io::ErrorKind::BrokenPipe) error if the
+    /// reader has been dropped, in which case the object is discarded.
+    pub async fn send(&mut self, item: T) -> io::Result<()> {
+        self.send_with(|buf| buf.push(item)).await
+    }
+
+    /// Send a batch of objects to the reader all at once.
+    ///
+    /// The objects are moved into one of the pipe's reusable batch buffers and
+    /// delivered together, which is much more efficient than sending the same
+    /// objects one at a time.
+    pub async fn send_batch(&mut self, batch: Vec<T>) -> io::Result<()> {
+        // Do not send empty batches through the rotation.
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        self.send_with(|buf| buf.extend(batch)).await
+    }
+
+    /// Close the pipe, signaling to the reader that no more objects will be
+    /// sent.
+    pub fn close(&mut self) {
+        self.buf_stream_tx.close();
+    }
+
+    async fn send_with(&mut self, fill: impl FnOnce(&mut Vec<T>)) -> io::Result<()> {
+        let mut buf = poll_fn(|cx| self.poll_buffer(cx)).await?;
+
+        fill(&mut buf);
+
+        if let Err(e) = self.buf_stream_tx.try_send(buf) {
+            if e.is_full() {
+                panic!("buffer pool overflow")
+            } else {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Wait for a batch buffer to be available to write to.
+    fn poll_buffer(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Vec<T>>> {
+        // Check if the reader is gone before waiting on the pool.
+        if self.buf_stream_tx.is_closed() {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+
+        match Pin::new(&mut self.buf_pool_rx).poll_next(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(None) => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+            Poll::Ready(Some(buf)) => Poll::Ready(Ok(buf)),
+        }
+    }
+}
+
+impl<T> fmt::Debug for ObjectWriter<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("ObjectWriter")
+    }
+}
@@ -0,0 +1,108 @@
+//! Limiting how fast bytes can be written into a pipe.
+//!
+//! The crate does not depend on any particular async runtime, so waiting is
+//! done with a sleep function provided by the user, such as one from their
+//! runtime's timer.
+
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+
+type Sleep = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A rate limit configured on a pipe builder.
+#[derive(Clone)]
+pub(crate) struct RateLimit {
+    bytes_per_sec: u64,
+    sleep: Arc<dyn Fn(Duration) -> Sleep + Send + Sync>,
+}
+
+impl RateLimit {
+    pub(crate) fn new<F, S>(bytes_per_sec: u64, sleep: F) -> Self
+    where
+        F: Fn(Duration) -> S + Send + Sync + 'static,
+        S: Future<Output = ()> + Send + 'static,
+    {
+        Self {
+            bytes_per_sec,
+            sleep: Arc::new(move |duration| Box::pin(sleep(duration))),
+        }
+    }
+}
+
+impl fmt::Debug for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimit")
+            .field("bytes_per_sec", &self.bytes_per_sec)
+            .finish()
+    }
+}
+
+/// The earliest time at which the next write may happen.
+///
+/// This is kept in the state shared by every clone of a writer, so that all of
+/// them draw from the same budget and the limit applies to the pipe as a whole.
+#[derive(Default)]
+pub(crate) struct Budget(Mutex<Option<Instant>>);
+
+/// Paces writes so that on average no more than the configured number of bytes
+/// are written per second.
+pub(crate) struct Limiter {
+    limit: RateLimit,
+
+    /// A sleep in progress until the next write may happen. Kept behind a lock
+    /// only so that the writer can be shared between threads.
+    delay: Mutex<Option<Sleep>>,
+}
+
+impl Limiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            delay: Mutex::new(None),
+        }
+    }
+
+    /// Wait until the next write is allowed.
+    pub(crate) fn poll_ready(&mut self, budget: &Budget, cx: &mut Context<'_>) -> Poll<()> {
+        let delay = self.delay.get_mut().unwrap();
+
+        loop {
+            if let Some(sleep) = delay.as_mut() {
+                ready!(sleep.as_mut().poll(cx));
+                *delay = None;
+            }
+
+            let now = Instant::now();
+
+            match *budget.0.lock().unwrap() {
+                Some(next) if next > now => {
+                    *delay = Some((self.limit.sleep)(next - now));
+                }
+                _ => return Poll::Ready(()),
+            }
+        }
+    }
+
+    /// Account for bytes that were just written, pushing back the time of the
+    /// next write accordingly.
+    pub(crate) fn record(&self, budget: &Budget, amt: usize) {
+        let mut next = budget.0.lock().unwrap();
+        let now = Instant::now();
+        let start = next.map_or(now, |next| next.max(now));
+        let cost = Duration::from_secs_f64(amt as f64 / self.limit.bytes_per_sec as f64);
+
+        *next = Some(start + cost);
+    }
+}
+
+impl Clone for Limiter {
+    fn clone(&self) -> Self {
+        Self::new(self.limit.clone())
+    }
+}
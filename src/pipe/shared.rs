@@ -0,0 +1,100 @@
+//! A pipe reader that can be shared between several tasks.
+
+use super::PipeReader;
+use futures_io::AsyncRead;
+use std::{
+    fmt,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Wake, Waker},
+};
+
+/// A cheaply cloneable handle to a pipe reader that can be read from by
+/// several tasks.
+///
+/// Every clone reads from the same pipe, so each byte is received by only one
+/// of them, whichever clone happens to read it first. The underlying reader is
+/// locked only for the duration of each individual read.
+#[derive(Clone)]
+pub struct SharedReader {
+    inner: Arc<Mutex<State>>,
+}
+
+struct State {
+    reader: PipeReader,
+
+    /// Every task waiting to read.
+    waiters: Arc<Waiters>,
+
+    /// Waker given to the pipe, which wakes all waiting tasks.
+    waker: Waker,
+}
+
+/// The tasks waiting to read from a shared reader.
+///
+/// The pipe only remembers the waker it was last polled with, and the task
+/// that polled last may give up on its read. Instead the pipe is always given
+/// a waker that wakes every waiting task, so none of them are left behind.
+#[derive(Default)]
+struct Waiters(Mutex<Vec<Waker>>);
+
+impl Waiters {
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.0.lock().unwrap();
+
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+    }
+}
+
+impl Wake for Waiters {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        for waker in std::mem::take(&mut *self.0.lock().unwrap()) {
+            waker.wake();
+        }
+    }
+}
+
+impl SharedReader {
+    /// Wrap a pipe reader so that it can be shared.
+    pub fn new(reader: PipeReader) -> Self {
+        let waiters = Arc::new(Waiters::default());
+
+        Self {
+            inner: Arc::new(Mutex::new(State {
+                reader,
+                waker: Waker::from(waiters.clone()),
+                waiters,
+            })),
+        }
+    }
+}
+
+impl AsyncRead for SharedReader {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let state = &mut *self.inner.lock().unwrap();
+
+        // Register before polling so that a wakeup arriving in between is not
+        // missed. At worst this causes a spurious wakeup if the read completes.
+        state.waiters.register(cx.waker());
+
+        let mut cx = Context::from_waker(&state.waker);
+        Pin::new(&mut state.reader).poll_read(&mut cx, buf)
+    }
+}
+
+impl fmt::Debug for SharedReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad("SharedReader")
+    }
+}
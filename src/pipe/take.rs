@@ -0,0 +1,90 @@
+//! A reader adapter that reads at most a fixed number of bytes from a pipe.
+
+use super::PipeReader;
+use futures_io::{AsyncBufRead, AsyncRead};
+use std::{
+    cmp,
+    io,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+/// A pipe reader that only reads up to a limited number of bytes.
+///
+/// Once the limit is reached, this reports the end of the stream while leaving
+/// the rest of the pipe unread, so that the underlying reader can be recovered
+/// with [`into_inner`](Take::into_inner) and read from further. Created by
+/// [`PipeReader::take`].
+#[derive(Debug)]
+pub struct Take {
+    inner: PipeReader,
+    limit: u64,
+}
+
+impl Take {
+    pub(crate) fn new(inner: PipeReader, limit: u64) -> Self {
+        Self { inner, limit }
+    }
+
+    /// Get the number of bytes that can still be read before reaching the
+    /// limit.
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Set the number of bytes that can be read before reaching the limit.
+    pub fn set_limit(&mut self, limit: u64) {
+        self.limit = limit;
+    }
+
+    /// Get a reference to the underlying pipe reader.
+    pub fn get_ref(&self) -> &PipeReader {
+        &self.inner
+    }
+
+    /// Get the underlying pipe reader, positioned right after the last byte
+    /// read through this reader.
+    pub fn into_inner(self) -> PipeReader {
+        self.inner
+    }
+}
+
+impl AsyncRead for Take {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.limit == 0 {
+            return Poll::Ready(Ok(0));
+        }
+
+        let max = cmp::min(buf.len() as u64, self.limit) as usize;
+        let amt = ready!(Pin::new(&mut self.inner).poll_read(cx, &mut buf[..max]))?;
+        self.limit -= amt as u64;
+
+        Poll::Ready(Ok(amt))
+    }
+}
+
+impl AsyncBufRead for Take {
+    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<&[u8]>> {
+        let this = self.get_mut();
+
+        if this.limit == 0 {
+            return Poll::Ready(Ok(&[]));
+        }
+
+        // Never expose bytes past the limit.
+        let buf = ready!(Pin::new(&mut this.inner).poll_fill_buf(cx))?;
+        let max = cmp::min(buf.len() as u64, this.limit) as usize;
+
+        Poll::Ready(Ok(&buf[..max]))
+    }
+
+    fn consume(mut self: Pin<&mut Self>, amt: usize) {
+        let amt = cmp::min(amt as u64, self.limit) as usize;
+        self.limit -= amt as u64;
+        Pin::new(&mut self.inner).consume(amt);
+    }
+}
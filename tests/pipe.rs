@@ -1,11 +1,31 @@
 use futures::{
     executor::block_on,
     join,
+    poll,
     prelude::*,
 };
 use quickcheck_macros::quickcheck;
-use sluice::pipe::pipe;
-use std::io;
+use sluice::pipe::{
+    codec::{Decoder, Encoder, FramedPipe, LengthCodec},
+    framed::{FramedReader, FramedWriter},
+    object_pipe,
+    pipe,
+    pump,
+    rendezvous,
+    BlockingReader,
+    PipeBuilder,
+    PipeConfig,
+    PipeWriter,
+    SharedReader,
+};
+use std::{
+    future::Future,
+    io,
+    io::Read,
+    sync::mpsc,
+    thread,
+    time::{Duration, Instant},
+};
 
 #[test]
 fn read_empty() {
@@ -90,6 +110,7 @@ fn pipe_lots_of_data() {
 }
 
 #[quickcheck]
+#[allow(clippy::clone_on_copy, clippy::unused_io_amount)]
 fn read_write_chunks_random(chunks: u8) {
     block_on(async {
         let data = [0; 8192];
@@ -111,3 +132,1663 @@ fn read_write_chunks_random(chunks: u8) {
         );
     })
 }
+
+#[test]
+fn initial_chunk_capacity() {
+    block_on(async {
+        let (mut reader, mut writer) = PipeBuilder::new()
+            .chunk_count(2)
+            .initial_chunk_capacity(1024)
+            .build();
+
+        writer.write_all(b"hello").await.unwrap();
+        writer.close().await.unwrap();
+
+        let mut out = String::new();
+        reader.read_to_string(&mut out).await.unwrap();
+        assert_eq!(out, "hello");
+    })
+}
+
+#[test]
+fn rendezvous_write_waits_for_read() {
+    block_on(async {
+        let (mut reader, mut writer) = rendezvous();
+        let mut dest = [0; 3];
+
+        let mut write = writer.write(b"hello");
+        assert!(poll!(&mut write).is_pending());
+
+        let mut read = reader.read(&mut dest);
+        assert!(poll!(&mut read).is_pending());
+
+        assert_eq!(write.await.unwrap(), 5);
+        assert_eq!(read.await.unwrap(), 3);
+        assert_eq!(&dest, b"hel");
+
+        // The next write waits until the rest has been read.
+        let mut write = writer.write(b"!");
+        assert!(poll!(&mut write).is_pending());
+
+        assert_eq!(reader.read(&mut dest).await.unwrap(), 2);
+        assert_eq!(&dest[..2], b"lo");
+        assert!(poll!(&mut write).is_pending());
+
+        let mut read = reader.read(&mut dest);
+        assert!(poll!(&mut read).is_pending());
+        assert_eq!(write.await.unwrap(), 1);
+        assert_eq!(read.await.unwrap(), 1);
+        assert_eq!(&dest[..1], b"!");
+    })
+}
+
+#[test]
+fn rendezvous_cancelled_write_sends_nothing() {
+    block_on(async {
+        let (mut reader, mut writer) = rendezvous();
+
+        let mut write = writer.write(b"hello world");
+        assert!(poll!(&mut write).is_pending());
+        drop(write);
+
+        join!(
+            async {
+                assert_eq!(writer.write(b"hi").await.unwrap(), 2);
+                writer.close().await.unwrap();
+            },
+            async {
+                let mut buf = Vec::new();
+                reader.read_to_end(&mut buf).await.unwrap();
+                assert_eq!(buf, b"hi");
+            },
+        );
+    })
+}
+
+#[test]
+fn rendezvous_write_reusing_cancelled_buffer_sends_new_contents() {
+    block_on(async {
+        let (mut reader, mut writer) = rendezvous();
+        let mut buf = *b"AAAA";
+
+        let mut write = writer.write(&buf);
+        assert!(poll!(&mut write).is_pending());
+        drop(write);
+
+        buf.copy_from_slice(b"BBBB");
+
+        join!(
+            async {
+                assert_eq!(writer.write(&buf).await.unwrap(), 4);
+                writer.close().await.unwrap();
+            },
+            async {
+                let mut out = Vec::new();
+                reader.read_to_end(&mut out).await.unwrap();
+                assert_eq!(out, b"BBBB");
+            },
+        );
+    })
+}
+
+#[test]
+fn rendezvous_writer_errors_if_reader_is_dropped_before_reading() {
+    block_on(async {
+        let (reader, mut writer) = rendezvous();
+
+        let mut write = writer.write(b"hello");
+        assert!(poll!(&mut write).is_pending());
+
+        drop(reader);
+
+        assert_eq!(write.await.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    })
+}
+
+#[test]
+fn max_chunk_retained_capacity() {
+    block_on(async {
+        let big = vec![0xff; 100_000];
+        let (mut reader, mut writer) = PipeBuilder::new()
+            .chunk_count(1)
+            .max_chunk_retained_capacity(16)
+            .build();
+
+        join!(
+            async {
+                writer.write_all(&big).await.unwrap();
+                writer.write_all(b"hello").await.unwrap();
+                writer.close().await.unwrap();
+            },
+            async {
+                let mut out = Vec::new();
+                reader.read_to_end(&mut out).await.unwrap();
+                assert_eq!(&out[..big.len()], &big[..]);
+                assert_eq!(&out[big.len()..], b"hello");
+            },
+        );
+    })
+}
+
+#[test]
+fn copy_into_buf() {
+    block_on(async {
+        let data = vec![0xff; 100_000];
+        let (reader, mut writer) = pipe();
+
+        join!(
+            async {
+                for chunk in data.chunks(1000) {
+                    writer.write_all(chunk).await.unwrap();
+                }
+                writer.close().await.unwrap();
+            },
+            async {
+                let mut out = Vec::new();
+                assert_eq!(reader.copy_into_buf(&mut out).await.unwrap(), data.len() as u64);
+                assert_eq!(out, data);
+            },
+        );
+    })
+}
+
+#[test]
+fn all_consumed_waits_for_reader() {
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+        let mut dest = [0; 3];
+
+        writer.write_all(b"hello").await.unwrap();
+        writer.write_all(b"world").await.unwrap();
+
+        let mut all_consumed = Box::pin(writer.all_consumed());
+        assert!(poll!(&mut all_consumed).is_pending());
+
+        reader.read_exact(&mut dest).await.unwrap();
+        reader.read_exact(&mut dest).await.unwrap();
+        reader.read_exact(&mut dest).await.unwrap();
+        assert!(poll!(&mut all_consumed).is_pending());
+
+        assert_eq!(reader.read(&mut dest).await.unwrap(), 1);
+        all_consumed.await.unwrap();
+    })
+}
+
+#[test]
+fn all_consumed_errors_if_reader_is_dropped() {
+    block_on(async {
+        let (reader, mut writer) = pipe();
+
+        writer.write_all(b"hello").await.unwrap();
+        drop(reader);
+
+        assert_eq!(writer.all_consumed().await.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    })
+}
+
+#[test]
+fn object_pipe_preserves_order() {
+    block_on(async {
+        let (reader, mut writer) = object_pipe();
+
+        join!(
+            async {
+                writer.send(1).await.unwrap();
+                writer.send_batch(vec![2, 3, 4]).await.unwrap();
+                writer.send_batch(Vec::new()).await.unwrap();
+                writer.send(5).await.unwrap();
+                for i in 6..100 {
+                    writer.send(i).await.unwrap();
+                }
+                writer.close();
+            },
+            async {
+                let items = reader.collect::<Vec<u32>>().await;
+                assert_eq!(items, (1..100).collect::<Vec<u32>>());
+            },
+        );
+    })
+}
+
+#[test]
+fn object_writer_errors_if_reader_is_dropped() {
+    block_on(async {
+        let (reader, mut writer) = object_pipe();
+
+        drop(reader);
+
+        assert_eq!(writer.send("hello").await.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    })
+}
+
+#[test]
+fn wait_idle_waits_for_reader_to_drain_and_park() {
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+        let mut dest = [0; 5];
+
+        writer.write_all(b"hello").await.unwrap();
+
+        let mut wait_idle = Box::pin(writer.wait_idle());
+        assert!(poll!(&mut wait_idle).is_pending());
+
+        reader.read_exact(&mut dest).await.unwrap();
+        assert!(poll!(&mut wait_idle).is_pending());
+
+        let mut read = reader.read(&mut dest);
+        assert!(poll!(&mut read).is_pending());
+
+        wait_idle.await.unwrap();
+    })
+}
+
+#[test]
+fn wait_idle_errors_if_reader_is_dropped() {
+    block_on(async {
+        let (reader, mut writer) = pipe();
+
+        drop(reader);
+
+        assert_eq!(writer.wait_idle().await.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    })
+}
+
+#[test]
+fn config_round_trip() {
+    let builder = PipeBuilder::new()
+        .chunk_count(8)
+        .initial_chunk_capacity(1024)
+        .max_chunk_retained_capacity(4096);
+
+    let config = builder.to_config();
+    assert_eq!(config.chunk_count, 8);
+    assert_eq!(config.initial_chunk_capacity, 1024);
+    assert_eq!(config.max_chunk_retained_capacity, Some(4096));
+    assert!(!config.rendezvous);
+
+    assert_eq!(PipeBuilder::from_config(config.clone()).to_config(), config);
+    assert_eq!(PipeConfig::default(), PipeBuilder::new().to_config());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn config_serde_round_trip() {
+    block_on(async {
+        let config = PipeBuilder::new()
+            .chunk_count(2)
+            .initial_chunk_capacity(64)
+            .to_config();
+
+        let json = serde_json::to_string(&config).unwrap();
+        let deserialized: PipeConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, config);
+
+        let builder = PipeBuilder::from_config(deserialized);
+        assert_eq!(builder.to_config(), config);
+
+        let (mut reader, mut writer) = builder.build();
+        writer.write_all(b"hello").await.unwrap();
+        writer.close().await.unwrap();
+
+        let mut out = String::new();
+        reader.read_to_string(&mut out).await.unwrap();
+        assert_eq!(out, "hello");
+    })
+}
+
+#[test]
+fn debug_shows_closed_and_buffered() {
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+
+        writer.write_all(b"hello").await.unwrap();
+        assert_eq!(format!("{:?}", writer), "PipeWriter { closed: false, buffered: 5 }");
+
+        let mut dest = [0; 2];
+        reader.read_exact(&mut dest).await.unwrap();
+        writer.close().await.unwrap();
+
+        assert_eq!(format!("{:?}", reader), "PipeReader { closed: true, buffered: 3 }");
+        assert_eq!(format!("{:?}", writer), "PipeWriter { closed: true, buffered: 3 }");
+    })
+}
+
+#[test]
+fn coalesced_writes_do_not_wait_for_reader() {
+    block_on(async {
+        let (mut reader, mut writer) = PipeBuilder::new()
+            .chunk_count(1)
+            .coalesce_writes(1024)
+            .build();
+
+        // Without coalescing, the second write would wait for the reader.
+        for _ in 0..100 {
+            writer.write_all(b"hello").await.unwrap();
+        }
+
+        // Nothing is visible to the reader until flushed.
+        assert_eq!(format!("{:?}", reader), "PipeReader { closed: false, buffered: 0 }");
+        writer.flush().await.unwrap();
+        writer.close().await.unwrap();
+
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).await.unwrap();
+        assert_eq!(out, b"hello".repeat(100));
+    })
+}
+
+#[test]
+fn coalesced_writes_are_sent_on_drop() {
+    block_on(async {
+        let (mut reader, mut writer) = PipeBuilder::new().coalesce_writes(1024).build();
+
+        writer.write_all(b"hello").await.unwrap();
+        drop(writer);
+
+        let mut out = String::new();
+        reader.read_to_string(&mut out).await.unwrap();
+        assert_eq!(out, "hello");
+    })
+}
+
+#[test]
+fn coalesced_writes_lots_of_data() {
+    block_on(async {
+        let data = (0..100_000).map(|i| i as u8).collect::<Vec<u8>>();
+        let (mut reader, mut writer) = PipeBuilder::new().coalesce_writes(4096).build();
+
+        join!(
+            async {
+                for chunk in data.chunks(97) {
+                    writer.write_all(chunk).await.unwrap();
+                }
+                writer.close().await.unwrap();
+            },
+            async {
+                let mut out = Vec::new();
+                reader.read_to_end(&mut out).await.unwrap();
+                assert_eq!(out, data);
+            },
+        );
+    })
+}
+
+#[test]
+fn read_drains_multiple_chunks() {
+    block_on(async {
+        let (mut reader, mut writer) = PipeBuilder::new().chunk_count(3).build();
+        let mut dest = [0; 16];
+
+        writer.write_all(b"hello").await.unwrap();
+        writer.write_all(b" ").await.unwrap();
+        writer.write_all(b"world").await.unwrap();
+
+        assert_eq!(reader.read(&mut dest).await.unwrap(), 11);
+        assert_eq!(&dest[..11], b"hello world");
+    })
+}
+
+#[test]
+fn capacity_is_chunk_count() {
+    let (reader, writer) = pipe();
+    assert_eq!(reader.capacity(), 4);
+    assert_eq!(writer.capacity(), 4);
+
+    let (reader, writer) = PipeBuilder::new().chunk_count(16).build();
+    assert_eq!(reader.capacity(), 16);
+    assert_eq!(writer.capacity(), 16);
+
+    let (reader, writer) = rendezvous();
+    assert_eq!(reader.capacity(), 1);
+    assert_eq!(writer.capacity(), 1);
+}
+
+#[test]
+fn writer_closed_resolves_when_reader_is_dropped() {
+    block_on(async {
+        let (reader, writer) = pipe();
+
+        let mut closed = Box::pin(writer.closed());
+        assert!(poll!(&mut closed).is_pending());
+
+        drop(reader);
+        closed.await;
+
+        // Closing the writer itself does not count.
+        let (_reader, mut writer) = pipe();
+        writer.close().await.unwrap();
+        assert!(poll!(Box::pin(writer.closed())).is_pending());
+    })
+}
+
+#[test]
+fn reader_eof_resolves_when_writer_is_closed_and_drained() {
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+
+        writer.write_all(b"hello").await.unwrap();
+        writer.close().await.unwrap();
+        assert!(poll!(Box::pin(reader.eof())).is_pending());
+
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).await.unwrap();
+        reader.eof().await;
+
+        let (reader, writer) = pipe();
+        let mut eof = Box::pin(reader.eof());
+        assert!(poll!(&mut eof).is_pending());
+
+        drop(writer);
+        eof.await;
+    })
+}
+
+#[test]
+fn send_all_writes_stream_and_closes() {
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+        let items = vec![Ok("hello"), Ok(" "), Ok("world")];
+
+        join!(
+            async {
+                writer.send_all(stream::iter(items)).await.unwrap();
+            },
+            async {
+                let mut out = String::new();
+                reader.read_to_string(&mut out).await.unwrap();
+                assert_eq!(out, "hello world");
+            },
+        );
+    })
+}
+
+#[test]
+fn send_all_returns_stream_errors() {
+    block_on(async {
+        let (_reader, mut writer) = pipe();
+        let items = vec![Ok(vec![1, 2, 3]), Err(io::ErrorKind::ConnectionReset.into())];
+
+        let err = writer.send_all(stream::iter(items)).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+    })
+}
+
+#[test]
+fn framed_round_trip() {
+    block_on(async {
+        let (reader, writer) = pipe();
+        let mut reader = FramedReader::new(reader);
+        let mut writer = FramedWriter::new(writer);
+
+        join!(
+            async {
+                writer.send(b"hello").await.unwrap();
+                writer.send(b"").await.unwrap();
+                writer.send(&[0xff; 10_000]).await.unwrap();
+                writer.close().await.unwrap();
+            },
+            async {
+                assert_eq!(reader.recv().await.unwrap().unwrap(), b"hello");
+                assert_eq!(reader.recv().await.unwrap().unwrap(), b"");
+                assert_eq!(reader.recv().await.unwrap().unwrap(), vec![0xff; 10_000]);
+                assert_eq!(reader.recv().await.unwrap(), None);
+            },
+        );
+    })
+}
+
+#[test]
+fn framed_reader_reassembles_split_frames() {
+    block_on(async {
+        let (reader, mut writer) = pipe();
+        let mut reader = FramedReader::new(reader);
+
+        join!(
+            async {
+                for byte in b"\0\0\0\x05hello\0\0" {
+                    writer.write_all(&[*byte]).await.unwrap();
+                }
+                writer.write_all(b"\0\x02hi").await.unwrap();
+                writer.close().await.unwrap();
+            },
+            async {
+                assert_eq!(reader.recv().await.unwrap().unwrap(), b"hello");
+                assert_eq!(reader.recv().await.unwrap().unwrap(), b"hi");
+                assert_eq!(reader.recv().await.unwrap(), None);
+            },
+        );
+    })
+}
+
+#[test]
+fn framed_reader_rejects_oversized_frames() {
+    block_on(async {
+        let (reader, mut writer) = pipe();
+        let mut reader = FramedReader::new(reader).max_frame_len(4);
+
+        writer.write_all(b"\0\0\0\x05hello").await.unwrap();
+        writer.write_all(b"\0\0\0\x01!").await.unwrap();
+
+        assert_eq!(reader.recv().await.unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        // The unread payload is never mistaken for the next frame.
+        assert_eq!(reader.recv().await.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(reader.next().await.is_none());
+    })
+}
+
+#[test]
+fn framed_reader_errors_on_truncated_frame() {
+    block_on(async {
+        let (reader, mut writer) = pipe();
+        let mut reader = FramedReader::new(reader);
+
+        writer.write_all(b"\0\0\0\x05hel").await.unwrap();
+        drop(writer);
+
+        assert_eq!(reader.recv().await.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    })
+}
+
+#[test]
+fn framed_writer_rejects_oversized_frames() {
+    block_on(async {
+        let (_reader, writer) = pipe();
+        let mut writer = FramedWriter::new(writer).max_frame_len(4);
+
+        assert_eq!(writer.send(b"hello").await.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    })
+}
+
+#[test]
+fn framed_pipe_sends_bytes_with_length_codec() {
+    block_on(async {
+        let (reader, writer) = pipe();
+        let mut framed = FramedPipe::new(reader, writer, LengthCodec);
+
+        framed.send(b"hello").await.unwrap();
+        framed.send("abcd").await.unwrap();
+        framed.send(&vec![1, 2]).await.unwrap();
+        framed.close().await.unwrap();
+
+        assert_eq!(framed.recv::<Vec<u8>>().await.unwrap().unwrap(), b"hello");
+        assert_eq!(framed.recv::<[u8; 4]>().await.unwrap().unwrap(), *b"abcd");
+        assert_eq!(
+            framed.recv::<[u8; 4]>().await.unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(framed.recv::<Vec<u8>>().await.unwrap(), None);
+    })
+}
+
+#[test]
+fn framed_pipe_uses_custom_codec_in_both_directions() {
+    struct U32Codec;
+
+    impl Encoder<u32> for U32Codec {
+        fn encode(&mut self, item: &u32, dst: &mut Vec<u8>) -> io::Result<()> {
+            dst.extend_from_slice(&item.to_le_bytes());
+            Ok(())
+        }
+    }
+
+    impl Decoder<u32> for U32Codec {
+        fn decode(&mut self, frame: &[u8]) -> io::Result<u32> {
+            let bytes = <[u8; 4] as std::convert::TryFrom<_>>::try_from(frame)
+                .map_err(|_| io::Error::from(io::ErrorKind::InvalidData))?;
+            Ok(u32::from_le_bytes(bytes))
+        }
+    }
+
+    block_on(async {
+        let (a_reader, b_writer) = pipe();
+        let (b_reader, a_writer) = pipe();
+        let mut a = FramedPipe::new(a_reader, a_writer, U32Codec);
+        let mut b = FramedPipe::new(b_reader, b_writer, U32Codec);
+
+        join!(
+            async {
+                for i in 0..100 {
+                    a.send(&i).await.unwrap();
+                    assert_eq!(a.recv::<u32>().await.unwrap(), Some(i * 2));
+                }
+                a.close().await.unwrap();
+            },
+            async {
+                while let Some(i) = b.recv::<u32>().await.unwrap() {
+                    b.send(&(i * 2)).await.unwrap();
+                }
+            },
+        );
+    })
+}
+
+#[test]
+fn framed_pipe_reassembles_values_split_across_chunks() {
+    block_on(async {
+        let (a_reader, mut b_writer) = PipeBuilder::new().chunk_count(16).build();
+        let (b_reader, a_writer) = pipe();
+
+        for byte in b"\0\0\0\x03abc" {
+            b_writer.write_all(&[*byte]).await.unwrap();
+        }
+
+        let mut a = FramedPipe::new(a_reader, a_writer, LengthCodec);
+        let mut b = FramedPipe::new(b_reader, b_writer, LengthCodec);
+        b.close().await.unwrap();
+
+        assert_eq!(a.recv::<Vec<u8>>().await.unwrap().unwrap(), b"abc");
+        assert_eq!(a.recv::<Vec<u8>>().await.unwrap(), None);
+    })
+}
+
+#[test]
+fn framed_pipe_enforces_max_frame_len() {
+    block_on(async {
+        let (reader, writer) = pipe();
+        let mut framed = FramedPipe::new(reader, writer, LengthCodec).max_frame_len(4);
+
+        assert_eq!(framed.send(b"hello").await.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        framed.send(b"hi").await.unwrap();
+        assert_eq!(framed.recv::<Vec<u8>>().await.unwrap().unwrap(), b"hi");
+    })
+}
+
+#[test]
+fn blocking_reader_reads_from_async_writer() {
+    let data = vec![0xff; 100_000];
+    let (reader, mut writer) = pipe();
+
+    let producer = thread::spawn({
+        let data = data.clone();
+        move || {
+            block_on(async {
+                for chunk in data.chunks(1000) {
+                    writer.write_all(chunk).await.unwrap();
+                }
+                writer.close().await.unwrap();
+            })
+        }
+    });
+
+    let mut out = Vec::new();
+    BlockingReader::new(reader).read_to_end(&mut out).unwrap();
+    assert_eq!(out, data);
+
+    producer.join().unwrap();
+}
+
+#[test]
+fn take_stops_at_limit_and_leaves_rest() {
+    block_on(async {
+        let (reader, mut writer) = pipe();
+
+        writer.write_all(b"hello").await.unwrap();
+        writer.write_all(b"world").await.unwrap();
+        writer.close().await.unwrap();
+
+        let mut take = reader.take(7);
+        assert_eq!(take.fill_buf().await.unwrap(), b"hello");
+        take.consume_unpin(5);
+        assert_eq!(take.fill_buf().await.unwrap(), b"wo");
+
+        let mut out = String::new();
+        take.read_to_string(&mut out).await.unwrap();
+        assert_eq!(out, "wo");
+        assert_eq!(take.limit(), 0);
+
+        let mut reader = take.into_inner();
+        out.clear();
+        reader.read_to_string(&mut out).await.unwrap();
+        assert_eq!(out, "rld");
+    })
+}
+
+#[test]
+fn read_buf_fills_spare_capacity() {
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+
+        writer.write_all(b"hello").await.unwrap();
+        writer.write_all(b"world").await.unwrap();
+        writer.close().await.unwrap();
+
+        let mut buf = Vec::with_capacity(8);
+        buf.extend_from_slice(b">");
+        let capacity = buf.capacity();
+
+        assert_eq!(reader.read_buf(&mut buf).await.unwrap(), capacity - 1);
+        assert_eq!(buf.capacity(), capacity);
+        assert_eq!(reader.read_buf(&mut buf).await.unwrap(), 0);
+
+        buf.reserve(100);
+        reader.read_buf(&mut buf).await.unwrap();
+        assert_eq!(buf, b">helloworld");
+        assert_eq!(reader.read_buf(&mut buf).await.unwrap(), 0);
+    })
+}
+
+#[test]
+fn close_and_wait_waits_for_reader_to_see_eof() {
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+        let mut dest = [0; 5];
+
+        writer.write_all(b"hello").await.unwrap();
+
+        let mut close = Box::pin(writer.close_and_wait());
+        assert!(poll!(&mut close).is_pending());
+
+        reader.read_exact(&mut dest).await.unwrap();
+        assert!(poll!(&mut close).is_pending());
+
+        assert_eq!(reader.read(&mut dest).await.unwrap(), 0);
+        close.await.unwrap();
+    })
+}
+
+#[test]
+fn close_and_wait_errors_if_reader_is_dropped() {
+    block_on(async {
+        let (reader, mut writer) = pipe();
+
+        writer.write_all(b"hello").await.unwrap();
+
+        let mut close = Box::pin(writer.close_and_wait());
+        assert!(poll!(&mut close).is_pending());
+
+        drop(reader);
+        assert_eq!(close.await.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    })
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn metrics_count_bytes_and_chunks() {
+    block_on(async {
+        let (mut reader, mut writer) = PipeBuilder::new().chunk_count(1).build();
+        let mut dest = [0; 3];
+
+        writer.write_all(b"hello").await.unwrap();
+        assert_eq!(writer.bytes_written(), 5);
+        assert_eq!(writer.chunks_written(), 1);
+        assert_eq!(reader.bytes_read(), 0);
+
+        let mut write = writer.write(b"world");
+        assert!(poll!(&mut write).is_pending());
+
+        reader.read_exact(&mut dest).await.unwrap();
+        assert_eq!(reader.bytes_read(), 3);
+        reader.read_exact(&mut dest[..2]).await.unwrap();
+        write.await.unwrap();
+
+        writer.close().await.unwrap();
+
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).await.unwrap();
+        assert_eq!(out, b"world");
+
+        assert_eq!(reader.bytes_read(), 10);
+        assert_eq!(writer.bytes_written(), 10);
+        assert_eq!(writer.chunks_written(), 2);
+        assert_eq!(writer.wait_count(), 1);
+    })
+}
+
+#[test]
+fn frames_stream() {
+    block_on(async {
+        let (reader, mut writer) = pipe();
+
+        join!(
+            async {
+                for byte in b"\0\0\0\x05hello\0\0\0\0\0\0" {
+                    writer.write_all(&[*byte]).await.unwrap();
+                }
+                writer.write_all(b"\0\x02hi").await.unwrap();
+                writer.close().await.unwrap();
+            },
+            async {
+                let frames = reader.frames(16).try_collect::<Vec<_>>().await.unwrap();
+                assert_eq!(frames, vec![b"hello".to_vec(), Vec::new(), b"hi".to_vec()]);
+            },
+        );
+    })
+}
+
+#[test]
+fn frames_stream_ends_after_error() {
+    block_on(async {
+        let (reader, mut writer) = pipe();
+
+        writer.write_all(b"\0\0\0\x02hi\0\0\0\x05hello").await.unwrap();
+
+        let mut frames = reader.frames(4);
+        assert_eq!(frames.next().await.unwrap().unwrap(), b"hi");
+        assert_eq!(frames.next().await.unwrap().unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert!(frames.next().await.is_none());
+    })
+}
+
+#[test]
+fn frames_stream_errors_on_truncated_frame() {
+    block_on(async {
+        let (reader, mut writer) = pipe();
+
+        writer.write_all(b"\0\0").await.unwrap();
+        drop(writer);
+
+        let mut frames = reader.frames(4);
+        assert_eq!(frames.next().await.unwrap().unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert!(frames.next().await.is_none());
+    })
+}
+
+#[test]
+fn drain_remaining_collects_buffered_chunks() {
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+
+        writer.write_all(b"hello ").await.unwrap();
+        writer.write_all(b"world").await.unwrap();
+
+        let mut buf = [0; 2];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(reader.drain_remaining(), b"llo world");
+        assert_eq!(reader.drain_remaining(), b"");
+
+        writer.write_all(b"!").await.unwrap();
+        drop(writer);
+
+        assert_eq!(reader.drain_remaining(), b"!");
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 0);
+    })
+}
+
+#[test]
+fn cloned_writers_write_into_the_same_pipe() {
+    block_on(async {
+        let (mut reader, writer) = pipe();
+        let mut writers = vec![writer.clone(), writer];
+
+        join!(
+            async {
+                for i in 0..10u8 {
+                    for writer in writers.iter_mut() {
+                        writer.write_all(&[i; 3]).await.unwrap();
+                    }
+                }
+
+                // The pipe stays open until every clone is closed.
+                writers[0].close().await.unwrap();
+                writers[1].write_all(b"!").await.unwrap();
+                drop(writers);
+            },
+            async {
+                let mut buf = Vec::new();
+                reader.read_to_end(&mut buf).await.unwrap();
+
+                assert_eq!(buf.len(), 61);
+                assert_eq!(buf.last(), Some(&b'!'));
+
+                for chunk in buf[..60].chunks(3) {
+                    assert!(chunk.iter().all(|&byte| byte == chunk[0]));
+                }
+            },
+        );
+    })
+}
+
+#[test]
+fn cloned_writers_share_all_consumed() {
+    block_on(async {
+        let (mut reader, mut writer) = PipeBuilder::new().chunk_count(2).build();
+        let mut clone = writer.clone();
+
+        writer.write_all(b"hello").await.unwrap();
+        assert!(poll!(Box::pin(writer.all_consumed())).is_pending());
+
+        let mut buf = [0; 5];
+        reader.read_exact(&mut buf).await.unwrap();
+        writer.all_consumed().await.unwrap();
+
+        // Spare buffers taken by one writer can still be used by the other.
+        clone.write_all(b"a").await.unwrap();
+        clone.write_all(b"b").await.unwrap();
+        drop(writer);
+        drop(clone);
+
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"ab");
+    })
+}
+
+/// Wait on two clones of a writer from separate threads, returning a channel
+/// that receives the result of each wait.
+fn wait_on_two_clones<F, Fut>(writer: &PipeWriter, wait: F) -> mpsc::Receiver<Fut::Output>
+where
+    F: Fn(PipeWriter) -> Fut + Clone + Send + 'static,
+    Fut: Future,
+    Fut::Output: Send + 'static,
+{
+    let (tx, rx) = mpsc::channel();
+
+    for _ in 0..2 {
+        let writer = writer.clone();
+        let wait = wait.clone();
+        let tx = tx.clone();
+
+        thread::spawn(move || tx.send(block_on(wait(writer))).unwrap());
+    }
+
+    // Give both threads time to start waiting.
+    thread::sleep(Duration::from_millis(100));
+
+    rx
+}
+
+#[test]
+fn cloned_writers_all_see_reader_dropped() {
+    let (reader, writer) = pipe();
+    let results = wait_on_two_clones(&writer, |writer| async move { writer.closed().await });
+
+    drop(reader);
+
+    for _ in 0..2 {
+        results.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+}
+
+#[test]
+fn cloned_writers_all_close_and_wait() {
+    let (mut reader, writer) = pipe();
+    let results = wait_on_two_clones(&writer, |writer| writer.close_and_wait());
+
+    drop(writer);
+    block_on(reader.read_to_end(&mut Vec::new())).unwrap();
+
+    for _ in 0..2 {
+        results.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+    }
+}
+
+#[test]
+fn cloned_writers_all_wait_idle() {
+    let (mut reader, writer) = pipe();
+    let results = wait_on_two_clones(&writer, |mut writer| async move { writer.wait_idle().await });
+
+    let consumer = thread::spawn(move || block_on(reader.read(&mut [0; 8])).unwrap());
+
+    for _ in 0..2 {
+        results.recv_timeout(Duration::from_secs(5)).unwrap().unwrap();
+    }
+
+    drop(writer);
+    assert_eq!(consumer.join().unwrap(), 0);
+}
+
+#[test]
+fn poll_ready_waits_for_a_free_chunk() {
+    block_on(async {
+        let (mut reader, mut writer) = PipeBuilder::new().chunk_count(1).build();
+
+        future::poll_fn(|cx| writer.poll_ready(cx)).await.unwrap();
+        writer.write_all(b"hello").await.unwrap();
+
+        let mut ready = future::poll_fn(|cx| writer.poll_ready(cx));
+        assert!(poll!(&mut ready).is_pending());
+
+        let mut buf = [0; 5];
+        reader.read_exact(&mut buf).await.unwrap();
+        ready.await.unwrap();
+
+        drop(reader);
+        assert_eq!(
+            future::poll_fn(|cx| writer.poll_ready(cx)).await.unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+    })
+}
+
+#[test]
+fn shared_reader_can_be_read_from_several_tasks() {
+    block_on(async {
+        let (reader, mut writer) = PipeBuilder::new().chunk_count(1).build();
+        let reader = SharedReader::new(reader);
+
+        async fn read_all(mut reader: SharedReader) -> usize {
+            let mut buf = Vec::new();
+            reader.read_to_end(&mut buf).await.unwrap();
+            buf.len()
+        }
+
+        let (_, a, b) = join!(
+            async {
+                for _ in 0..10 {
+                    writer.write_all(b"hello").await.unwrap();
+                }
+                writer.close().await.unwrap();
+            },
+            read_all(reader.clone()),
+            read_all(reader),
+        );
+
+        assert_eq!(a + b, 50);
+    })
+}
+
+#[test]
+fn shared_reader_wakes_waiters_after_another_read_is_cancelled() {
+    let (reader, mut writer) = pipe();
+    let reader = SharedReader::new(reader);
+    let (tx, rx) = mpsc::channel();
+
+    let mut waiting = reader.clone();
+    thread::spawn(move || {
+        let mut buf = [0; 5];
+        tx.send(block_on(waiting.read(&mut buf)).unwrap()).unwrap();
+    });
+    thread::sleep(Duration::from_millis(100));
+
+    block_on(async {
+        let mut cancelled = reader.clone();
+        let mut buf = [0; 5];
+        assert!(poll!(Box::pin(cancelled.read(&mut buf))).is_pending());
+
+        writer.write_all(b"hello").await.unwrap();
+    });
+
+    assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), 5);
+}
+
+#[test]
+fn copy_into_limited_leaves_the_rest_in_the_pipe() {
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+
+        writer.write_all(b"hello ").await.unwrap();
+        writer.write_all(b"world").await.unwrap();
+        drop(writer);
+
+        let mut sink = Vec::new();
+        assert_eq!(reader.copy_into_limited(&mut sink, 8).await.unwrap(), 8);
+        assert_eq!(sink, b"hello wo");
+
+        let mut sink = Vec::new();
+        assert_eq!(reader.copy_into_limited(&mut sink, 100).await.unwrap(), 3);
+        assert_eq!(sink, b"rld");
+    })
+}
+
+#[test]
+fn read_to_end_bounded_reads_everything_within_limit() {
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+
+        writer.write_all(b"hello ").await.unwrap();
+        writer.write_all(b"world").await.unwrap();
+        drop(writer);
+
+        let mut buf = b">".to_vec();
+        assert_eq!(reader.read_to_end_bounded(&mut buf, 11).await.unwrap(), 11);
+        assert_eq!(buf, b">hello world");
+    })
+}
+
+#[test]
+fn read_to_end_bounded_errors_when_limit_is_exceeded() {
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+
+        writer.write_all(b"hello ").await.unwrap();
+        writer.write_all(b"world").await.unwrap();
+        drop(writer);
+
+        let mut buf = Vec::new();
+        let err = reader.read_to_end_bounded(&mut buf, 8).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(buf, b"hello wo");
+
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b"rld");
+    })
+}
+
+#[test]
+fn write_all_counted_reports_progress_on_broken_pipe() {
+    block_on(async {
+        let (reader, mut writer) = pipe();
+
+        writer.write_all_counted(b"hello").await.unwrap();
+        drop(reader);
+
+        let (written, e) = writer.write_all_counted(b"world").await.unwrap_err();
+        assert_eq!(written, 0);
+        assert_eq!(e.kind(), io::ErrorKind::BrokenPipe);
+    })
+}
+
+#[test]
+fn positions_track_bytes_written_and_read() {
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+
+        writer.write_all(b"hello").await.unwrap();
+        writer.write_all(b"world").await.unwrap();
+        assert_eq!(writer.position(), 10);
+        assert_eq!(writer.clone().position(), 0);
+
+        let mut buf = [0; 3];
+        reader.read_exact(&mut buf).await.unwrap();
+        reader.fill_buf().await.unwrap();
+        reader.consume_unpin(1);
+        assert_eq!(reader.position(), 4);
+
+        reader.drain_remaining();
+        assert_eq!(reader.position(), writer.position());
+    })
+}
+
+#[test]
+fn reset_makes_a_closed_pipe_reusable() {
+    block_on(async {
+        let (mut reader, mut writer) = PipeBuilder::new().chunk_count(2).build();
+
+        writer.write_all(b"hello").await.unwrap();
+        writer.write_all(b"unread").await.unwrap();
+        writer.close().await.unwrap();
+
+        let mut buf = [0; 5];
+        reader.read_exact(&mut buf).await.unwrap();
+
+        reader.reset(&mut writer).unwrap();
+        assert_eq!(reader.position(), 0);
+        assert_eq!(writer.position(), 0);
+
+        writer.write_all(b"a").await.unwrap();
+        writer.write_all(b"b").await.unwrap();
+        drop(writer);
+
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"ab");
+    })
+}
+
+#[test]
+fn reset_rejects_halves_of_different_pipes() {
+    let (mut reader, _writer) = pipe();
+    let (_reader, mut other_writer) = pipe();
+
+    assert_eq!(
+        reader.reset(&mut other_writer).unwrap_err().kind(),
+        io::ErrorKind::InvalidInput
+    );
+
+    let (mut reader, mut writer) = pipe();
+    let _clone = writer.clone();
+
+    assert_eq!(reader.reset(&mut writer).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn pump_transforms_between_pipes() {
+    block_on(async {
+        let (source_reader, mut source_writer) = pipe();
+        let (mut sink_reader, sink_writer) = pipe();
+
+        let (_, result, output) = join!(
+            async {
+                source_writer.write_all(b"hello ").await.unwrap();
+                source_writer.write_all(b"world").await.unwrap();
+                drop(source_writer);
+            },
+            pump(source_reader, sink_writer, |chunk, output| {
+                output.extend(chunk.iter().map(u8::to_ascii_uppercase));
+            }),
+            async {
+                let mut buf = String::new();
+                sink_reader.read_to_string(&mut buf).await.unwrap();
+                buf
+            },
+        );
+
+        result.unwrap();
+        assert_eq!(output, "HELLO WORLD");
+    })
+}
+
+#[test]
+fn pump_stops_if_the_sink_is_dropped() {
+    block_on(async {
+        let (source_reader, mut source_writer) = pipe();
+        let (sink_reader, sink_writer) = pipe();
+
+        source_writer.write_all(b"hello").await.unwrap();
+        drop(sink_reader);
+
+        let result = pump(source_reader, sink_writer, |chunk, output| {
+            output.extend_from_slice(chunk);
+        });
+
+        assert_eq!(result.await.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+
+        assert!(source_writer.write_all(b"more").await.is_err());
+    })
+}
+
+#[test]
+fn fill_ratio_counts_unread_chunks() {
+    block_on(async {
+        let (mut reader, mut writer) = PipeBuilder::new().chunk_count(4).build();
+
+        assert_eq!(reader.fill_ratio(), 0.0);
+        assert!(reader.is_nearly_full(0.0));
+        assert!(!reader.is_nearly_full(0.25));
+
+        writer.write_all(b"a").await.unwrap();
+        assert_eq!(reader.fill_ratio(), 0.25);
+
+        for _ in 0..3 {
+            writer.write_all(b"b").await.unwrap();
+        }
+        assert_eq!(reader.fill_ratio(), 1.0);
+        assert!(reader.is_nearly_full(1.0));
+
+        let mut buf = [0; 1];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(reader.fill_ratio(), 0.75);
+        assert!(!reader.is_nearly_full(0.8));
+    })
+}
+
+#[test]
+fn lifo_chunk_reuse() {
+    block_on(async {
+        let (mut reader, mut writer) = PipeBuilder::new().lifo_chunk_reuse(true).build();
+
+        join!(
+            async {
+                for i in 0..20u8 {
+                    writer.write_all(&[i; 10]).await.unwrap();
+                }
+                writer.close().await.unwrap();
+            },
+            async {
+                let mut buf = Vec::new();
+                reader.read_to_end(&mut buf).await.unwrap();
+
+                let expected: Vec<u8> = (0..20u8).flat_map(|i| [i; 10]).collect();
+                assert_eq!(buf, expected);
+            },
+        );
+    })
+}
+
+#[test]
+fn read_exact_counted() {
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+
+        writer.write_all(b"hel").await.unwrap();
+        writer.write_all(b"lo world").await.unwrap();
+        drop(writer);
+
+        let mut buf = [0; 5];
+        reader.read_exact_counted(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        let mut buf = [0; 6];
+        reader.read_exact_counted(&mut buf).await.unwrap();
+        assert_eq!(&buf, b" world");
+    })
+}
+
+#[test]
+fn read_exact_counted_reports_progress_on_early_eof() {
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+
+        writer.write_all(b"abc").await.unwrap();
+        drop(writer);
+
+        let mut buf = [0; 8];
+        let (read, e) = reader.read_exact_counted(&mut buf).await.unwrap_err();
+        assert_eq!(read, 3);
+        assert_eq!(&buf[..read], b"abc");
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    })
+}
+
+#[test]
+fn read_integers_split_across_chunks() {
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+
+        writer.write_all(&[0x12]).await.unwrap();
+        writer.write_all(&[0x34, 0x12, 0x34, 0x56]).await.unwrap();
+        writer.write_all(&[0x78, 1, 2, 3]).await.unwrap();
+        writer.write_all(&[4, 5, 6, 7, 8, 0xff]).await.unwrap();
+        drop(writer);
+
+        assert_eq!(reader.read_u16_be().await.unwrap(), 0x1234);
+        assert_eq!(reader.read_u32_le().await.unwrap(), 0x7856_3412);
+        assert_eq!(reader.read_u64_be().await.unwrap(), 0x0102_0304_0506_0708);
+        assert_eq!(reader.read_u16_le().await.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    })
+}
+
+#[test]
+fn reserve_writes_in_place() {
+    struct Point {
+        x: u32,
+        y: u32,
+    }
+
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+        let point = Point { x: 1, y: 0x0203 };
+
+        let mut chunk = writer.reserve(16).await.unwrap();
+        chunk[..4].copy_from_slice(&point.x.to_be_bytes());
+        chunk[4..8].copy_from_slice(&point.y.to_be_bytes());
+        chunk.commit(8).await.unwrap();
+
+        // Dropping a reservation sends nothing.
+        let mut chunk = writer.reserve(4).await.unwrap();
+        chunk.copy_from_slice(b"nope");
+        drop(chunk);
+
+        writer.write_all(b"!").await.unwrap();
+        drop(writer);
+
+        assert_eq!(reader.read_u32_be().await.unwrap(), point.x);
+        assert_eq!(reader.read_u32_be().await.unwrap(), point.y);
+
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).await.unwrap();
+        assert_eq!(rest, b"!");
+    })
+}
+
+#[test]
+fn cancelled_read_does_not_lose_bytes() {
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+        let mut buf = [0; 64];
+
+        // Cancel a read that is waiting for the writer.
+        assert!(poll!(Box::pin(reader.read(&mut buf))).is_pending());
+
+        writer.write_all(b"hello").await.unwrap();
+
+        // Cancel a copy that has taken a chunk but cannot write it anywhere.
+        let (_blocked_reader, mut blocked_writer) = PipeBuilder::new().chunk_count(1).build();
+        blocked_writer.write_all(b"full").await.unwrap();
+        assert!(poll!(Box::pin(reader.copy_into_limited(&mut blocked_writer, 5))).is_pending());
+
+        writer.write_all(b" world").await.unwrap();
+        drop(writer);
+
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"hello world");
+    })
+}
+
+#[test]
+fn available_chunks() {
+    block_on(async {
+        let (mut reader, mut writer) = PipeBuilder::new().chunk_count(2).build();
+        assert_eq!(writer.available_chunks(), 2);
+
+        writer.write_all(b"a").await.unwrap();
+        writer.write_all(b"b").await.unwrap();
+        assert_eq!(writer.available_chunks(), 0);
+
+        let mut buf = [0; 1];
+        reader.read_exact(&mut buf).await.unwrap();
+        assert_eq!(writer.available_chunks(), 1);
+
+        future::poll_fn(|cx| writer.poll_ready(cx)).await.unwrap();
+        assert_eq!(writer.available_chunks(), 1);
+    })
+}
+
+#[test]
+fn rate_limit_paces_writes() {
+    block_on(async {
+        let (mut reader, mut writer) = PipeBuilder::new()
+            .rate_limit(10_000, |duration| async move { thread::sleep(duration) })
+            .build();
+
+        let start = Instant::now();
+
+        join!(
+            async {
+                for _ in 0..6 {
+                    writer.write_all(&[0; 500]).await.unwrap();
+                }
+                writer.close().await.unwrap();
+            },
+            async {
+                let mut buf = Vec::new();
+                reader.read_to_end(&mut buf).await.unwrap();
+                assert_eq!(buf.len(), 3000);
+            },
+        );
+
+        // The first write is free, and each after that waits 50ms.
+        assert!(start.elapsed() >= Duration::from_millis(250));
+    })
+}
+
+#[test]
+fn rate_limit_is_shared_by_cloned_writers() {
+    let (mut reader, writer) = PipeBuilder::new()
+        .chunk_count(8)
+        .rate_limit(10_000, |duration| async move { thread::sleep(duration) })
+        .build();
+
+    let start = Instant::now();
+
+    let producers = (0..2)
+        .map(|_| {
+            let mut writer = writer.clone();
+
+            thread::spawn(move || {
+                block_on(async {
+                    for _ in 0..3 {
+                        writer.write_all(&[0; 500]).await.unwrap();
+                    }
+                })
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(writer);
+
+    let mut buf = Vec::new();
+    block_on(reader.read_to_end(&mut buf)).unwrap();
+    assert_eq!(buf.len(), 3000);
+
+    for producer in producers {
+        producer.join().unwrap();
+    }
+
+    // Each clone on its own budget would finish after 100ms. Sharing one
+    // budget, they can overlap at most one write each.
+    assert!(start.elapsed() >= Duration::from_millis(200));
+}
+
+#[test]
+fn drain_discards_until_eof() {
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+
+        join!(
+            async {
+                for _ in 0..10 {
+                    writer.write_all(b"hello").await.unwrap();
+                }
+                writer.close().await.unwrap();
+            },
+            async {
+                assert_eq!(reader.drain().await.unwrap(), 50);
+                assert_eq!(reader.drain().await.unwrap(), 0);
+            },
+        );
+    })
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn stats_record_write_and_chunk_sizes() {
+    block_on(async {
+        let (mut reader, mut writer) = PipeBuilder::new().coalesce_writes(8).build();
+        assert_eq!(writer.stats(), Default::default());
+
+        writer.write_all(b"ab").await.unwrap();
+        writer.write_all(b"cdef").await.unwrap();
+        writer.write_all(b"ghijklmnop").await.unwrap();
+        writer.flush().await.unwrap();
+
+        let stats = writer.stats();
+        assert_eq!(stats.writes, 3);
+        assert_eq!(stats.min_write, 2);
+        assert_eq!(stats.max_write, 10);
+        assert_eq!(stats.avg_write, 5);
+        assert_eq!(stats.chunks, 2);
+        assert_eq!(stats.min_chunk, 6);
+        assert_eq!(stats.max_chunk, 10);
+        assert_eq!(stats.avg_chunk, 8);
+
+        drop(writer);
+        assert_eq!(reader.drain().await.unwrap(), 16);
+    })
+}
+
+#[test]
+fn poll_fill_at_least_waits_for_enough_bytes() {
+    block_on(async {
+        let (mut reader, mut writer) = PipeBuilder::new().chunk_count(2).build();
+
+        {
+            let mut fill = future::poll_fn(|cx| reader.poll_fill_at_least(cx, 6));
+
+            for byte in b"hello" {
+                writer.write_all(&[*byte]).await.unwrap();
+                assert!(poll!(&mut fill).is_pending());
+            }
+
+            writer.write_all(b" world").await.unwrap();
+            fill.await.unwrap();
+        }
+
+        assert_eq!(reader.fill_buf().await.unwrap(), b"hello world");
+        reader.consume_unpin(11);
+
+        writer.write_all(b"abc").await.unwrap();
+        drop(writer);
+
+        // Ends early if the pipe is closed.
+        future::poll_fn(|cx| reader.poll_fill_at_least(cx, 8)).await.unwrap();
+        assert_eq!(reader.fill_buf().await.unwrap(), b"abc");
+    })
+}
+
+#[test]
+fn write_from_reader() {
+    block_on(async {
+        let (mut reader, mut writer) = pipe();
+        let data: Vec<u8> = (0..20_000u32).map(|i| i as u8).collect();
+
+        join!(
+            async {
+                let mut source = io::Cursor::new(&data);
+                assert_eq!(writer.write_from_reader(&mut source).await.unwrap(), 20_000);
+                writer.close().await.unwrap();
+            },
+            async {
+                let mut buf = Vec::new();
+                reader.read_to_end(&mut buf).await.unwrap();
+                assert_eq!(buf, data);
+            },
+        );
+    })
+}
+
+#[cfg(feature = "chunk-accounting")]
+#[test]
+fn chunk_buffers_are_never_lost_or_duplicated() {
+    block_on(async {
+        let (mut reader, mut writer) = PipeBuilder::new()
+            .chunk_count(3)
+            .coalesce_writes(16)
+            .lifo_chunk_reuse(true)
+            .build();
+
+        for i in 0..500usize {
+            match i % 6 {
+                0 => writer.write_all(&[1; 7]).await.unwrap(),
+                1 => writer.write_all(&[2; 40]).await.unwrap(),
+                2 => writer.reserve(8).await.unwrap().commit(i % 9).await.unwrap(),
+                3 => drop(writer.reserve(8).await.unwrap()),
+                4 => future::poll_fn(|cx| writer.poll_ready(cx)).await.unwrap(),
+                _ => writer.flush().await.unwrap(),
+            }
+            reader.assert_chunk_accounting();
+
+            match i % 4 {
+                0 => {
+                    reader.drain_remaining();
+                }
+                1 => {
+                    writer.write_all(&[3]).await.unwrap();
+                    writer.flush().await.unwrap();
+                    future::poll_fn(|cx| reader.poll_fill_at_least(cx, 1)).await.unwrap();
+                    let amt = reader.fill_buf().await.unwrap().len();
+                    reader.consume_unpin(amt / 2);
+                    reader.drain_remaining();
+                }
+                2 if i % 3 == 0 => {
+                    writer.flush().await.unwrap();
+                    reader.drain_remaining();
+                    writer.all_consumed().await.unwrap();
+                }
+                _ => {
+                    writer.flush().await.unwrap();
+                    reader.drain_remaining();
+                }
+            }
+            reader.assert_chunk_accounting();
+
+            if i % 50 == 49 {
+                writer.write_all(&[4; 3]).await.unwrap();
+                reader.reset(&mut writer).unwrap();
+                reader.assert_chunk_accounting();
+            }
+        }
+    })
+}
+
+#[test]
+fn chunk_buffers_are_exchanged_between_threads() {
+    let (mut reader, mut writer) = PipeBuilder::new().chunk_count(1).build();
+
+    let producer = thread::spawn(move || {
+        block_on(async {
+            for _ in 0..20_000 {
+                writer.write_all(&[1]).await.unwrap();
+            }
+        })
+    });
+
+    let mut buf = Vec::new();
+    block_on(reader.read_to_end(&mut buf)).unwrap();
+    producer.join().unwrap();
+
+    assert_eq!(buf.len(), 20_000);
+}